@@ -0,0 +1,427 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use libc;
+
+use super::util::io_error;
+
+pub const SOCKET_ENV: &str = "EAKIO_AGENT_SOCK";
+
+const OP_GET: u8 = 0x01;
+const OP_PUT: u8 = 0x02;
+const OP_LOCK: u8 = 0x03;
+const OP_STOP: u8 = 0x04;
+
+const STATUS_OK: u8 = 0x00;
+const STATUS_EMPTY: u8 = 0x01;
+const STATUS_ERROR: u8 = 0x02;
+
+const MAX_SECRET: usize = 4096;
+const IO_TIMEOUT: u64 = 5;
+
+/// agent socket, $EAKIO_AGENT_SOCK, or in runtime dir only user can access
+pub fn socket_path() -> PathBuf {
+    if let Ok(path) = env::var(SOCKET_ENV) {
+        return PathBuf::from(path);
+    }
+
+    let dir = match env::var("XDG_RUNTIME_DIR") {
+        Ok(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => env::temp_dir().join(format!("eakio-{}", unsafe { libc::geteuid() })),
+    };
+    dir.join("eakio-agent.sock")
+}
+
+// socket dir maybe in shared tmp, someone else could create it first
+fn check_private_dir(dir: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(dir)?;
+    let uid = unsafe { libc::geteuid() };
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        return Err(io_error(&format!(
+            "agent dir {:?} not private, need a dir of uid {} with mode 0700",
+            dir, uid
+        )));
+    }
+    Ok(())
+}
+
+// secret in locked memory, never swapped, zeroed when wiped
+struct Secret {
+    buf: Box<[u8; MAX_SECRET]>,
+    len: usize,
+    expire: Option<Instant>,
+    locked: bool,
+}
+
+impl Secret {
+    fn new() -> Secret {
+        let buf = Box::new([0u8; MAX_SECRET]);
+        let ret = unsafe { libc::mlock(buf.as_ptr() as *const libc::c_void, MAX_SECRET) };
+        if ret != 0 {
+            warn!("mlock secret memory error, {}", io::Error::last_os_error());
+        }
+
+        Secret {
+            buf,
+            len: 0,
+            expire: None,
+            locked: ret == 0,
+        }
+    }
+
+    fn set(&mut self, data: &[u8], timeout: Duration) -> io::Result<()> {
+        if data.len() > MAX_SECRET {
+            return Err(io_error("secret too long"));
+        }
+
+        self.wipe();
+        self.buf[..data.len()].copy_from_slice(data);
+        self.len = data.len();
+        self.expire = Some(Instant::now() + timeout);
+        Ok(())
+    }
+
+    fn get(&mut self) -> Option<&[u8]> {
+        match self.expire {
+            Some(expire) if Instant::now() < expire => Some(&self.buf[..self.len]),
+            Some(_) => {
+                self.wipe();
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn wipe(&mut self) {
+        // volatile, not optimized out
+        for b in self.buf.iter_mut() {
+            unsafe { ptr::write_volatile(b, 0) };
+        }
+        self.len = 0;
+        self.expire = None;
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.wipe();
+        if self.locked {
+            unsafe { libc::munlock(self.buf.as_ptr() as *const libc::c_void, MAX_SECRET) };
+        }
+    }
+}
+
+/// passphrase caching agent, one secret kept until timeout or lock
+pub struct Agent {
+    path: PathBuf,
+    listener: UnixListener,
+    timeout: Duration,
+}
+
+impl Agent {
+    pub fn bind(path: &Path, timeout: Duration) -> io::Result<Agent> {
+        if let Some(dir) = path.parent() {
+            if !dir.exists() {
+                fs::DirBuilder::new()
+                    .recursive(true)
+                    .mode(0o700)
+                    .create(dir)?;
+            }
+            check_private_dir(dir)?;
+        }
+
+        if path.exists() {
+            // socket left by crashed agent
+            if UnixStream::connect(path).is_ok() {
+                return Err(io_error(&format!("agent already running at {:?}", path)));
+            }
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+        Ok(Agent {
+            path: path.to_path_buf(),
+            listener,
+            timeout,
+        })
+    }
+
+    pub fn serve(&self) -> io::Result<()> {
+        disable_core_dump();
+
+        let secret = Arc::new(Mutex::new(Secret::new()));
+
+        // wipe expired secret, even no one ask it
+        {
+            let secret = secret.clone();
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_secs(1));
+                secret.lock().unwrap().get();
+            });
+        }
+
+        let uid = unsafe { libc::geteuid() };
+        for stream in self.listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("agent accept error, {}", e);
+                    continue;
+                }
+            };
+
+            match peer_uid(&stream) {
+                Ok(peer) if peer == uid => {}
+                Ok(peer) => {
+                    warn!("agent reject connection from uid {}", peer);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("agent get peer uid error, {}", e);
+                    continue;
+                }
+            }
+
+            let mut guard = secret.lock().unwrap();
+            match self.handle(&mut stream, &mut guard) {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) => warn!("agent request error, {}", e),
+            }
+        }
+
+        fs::remove_file(&self.path)
+    }
+
+    // returns true if agent should stop
+    fn handle(&self, stream: &mut UnixStream, secret: &mut Secret) -> io::Result<bool> {
+        stream.set_read_timeout(Some(Duration::from_secs(IO_TIMEOUT)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(IO_TIMEOUT)))?;
+
+        let (op, mut payload) = read_frame(stream)?;
+        let stop = match op {
+            OP_GET => {
+                match secret.get() {
+                    Some(data) => write_frame(stream, STATUS_OK, data)?,
+                    None => write_frame(stream, STATUS_EMPTY, &[])?,
+                }
+                false
+            }
+            OP_PUT => {
+                let result = secret.set(&payload, self.timeout);
+                wipe_vec(&mut payload);
+                match result {
+                    Ok(()) => write_frame(stream, STATUS_OK, &[])?,
+                    Err(e) => write_frame(stream, STATUS_ERROR, format!("{}", e).as_bytes())?,
+                }
+                false
+            }
+            OP_LOCK => {
+                secret.wipe();
+                write_frame(stream, STATUS_OK, &[])?;
+                info!("agent locked");
+                false
+            }
+            OP_STOP => {
+                secret.wipe();
+                write_frame(stream, STATUS_OK, &[])?;
+                info!("agent stopped");
+                true
+            }
+            _ => {
+                write_frame(stream, STATUS_ERROR, b"unknown request")?;
+                false
+            }
+        };
+
+        Ok(stop)
+    }
+}
+
+// one request or response, OP or STATUS, then data with its length
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let op = reader.read_u8()?;
+    let len = reader.read_u32::<BigEndian>()? as usize;
+    if len > MAX_SECRET {
+        return Err(io_error("agent frame too large"));
+    }
+
+    let mut data = vec![0u8; len];
+    reader.read_exact(&mut data)?;
+    Ok((op, data))
+}
+
+fn write_frame<W: Write>(writer: &mut W, op: u8, data: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(5 + data.len());
+    frame.write_u8(op)?;
+    frame.write_u32::<BigEndian>(data.len() as u32)?;
+    frame.extend_from_slice(data);
+    writer.write_all(&frame)?;
+    wipe_vec(&mut frame);
+    Ok(())
+}
+
+fn wipe_vec(data: &mut [u8]) {
+    for b in data.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0) };
+    }
+}
+
+fn request(op: u8, data: &[u8]) -> io::Result<(u8, Vec<u8>)> {
+    let mut stream = UnixStream::connect(socket_path())?;
+    // socket maybe made by other user first, never send secret to it
+    let uid = unsafe { libc::geteuid() };
+    let peer = peer_uid(&stream)?;
+    if peer != uid {
+        return Err(io_error(&format!("agent run by uid {}, not trusted", peer)));
+    }
+    stream.set_read_timeout(Some(Duration::from_secs(IO_TIMEOUT)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(IO_TIMEOUT)))?;
+
+    write_frame(&mut stream, op, data)?;
+    read_frame(&mut stream)
+}
+
+fn request_ok(op: u8, data: &[u8]) -> io::Result<()> {
+    match request(op, data)? {
+        (STATUS_OK, _) => Ok(()),
+        (_, msg) => Err(io_error(&String::from_utf8_lossy(&msg))),
+    }
+}
+
+/// secret cached in agent, None if agent not running or locked
+pub fn get_secret() -> Option<Vec<u8>> {
+    match request(OP_GET, &[]) {
+        Ok((STATUS_OK, secret)) => Some(secret),
+        Ok(_) => None,
+        Err(e) => {
+            debug!("agent not available, {}", e);
+            None
+        }
+    }
+}
+
+/// cache secret in agent if it is running
+pub fn put_secret(secret: &[u8]) {
+    if !socket_path().exists() {
+        return;
+    }
+    if let Err(e) = request_ok(OP_PUT, secret) {
+        debug!("agent put secret error, {}", e);
+    }
+}
+
+pub fn lock() -> io::Result<()> {
+    request_ok(OP_LOCK, &[])
+}
+
+pub fn stop() -> io::Result<()> {
+    request_ok(OP_STOP, &[])
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    use std::mem;
+
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(cred.uid)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let mut uid = 0;
+    let mut gid = 0;
+    let ret = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(uid)
+}
+
+// core dump may contain the secret
+#[cfg(target_os = "linux")]
+fn disable_core_dump() {
+    unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0) };
+}
+
+#[cfg(not(target_os = "linux"))]
+fn disable_core_dump() {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_frame() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, OP_PUT, b"secret").unwrap();
+        assert_eq!(buf.len(), 5 + 6);
+
+        let (op, data) = read_frame(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(op, OP_PUT);
+        assert_eq!(data, b"secret");
+
+        let mut big = Vec::new();
+        big.write_u8(OP_PUT).unwrap();
+        big.write_u32::<BigEndian>(MAX_SECRET as u32 + 1).unwrap();
+        assert!(read_frame(&mut Cursor::new(&big)).is_err());
+    }
+
+    #[test]
+    fn test_check_private_dir() {
+        use std::process;
+
+        let dir = env::temp_dir().join(format!("eakio-test-agent-{}", process::id()));
+        fs::DirBuilder::new().mode(0o700).create(&dir).unwrap();
+        assert!(check_private_dir(&dir).is_ok());
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(check_private_dir(&dir).is_err());
+
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_secret_expire() {
+        let mut secret = Secret::new();
+        assert_eq!(secret.get(), None);
+
+        secret.set(b"pass", Duration::from_secs(60)).unwrap();
+        assert_eq!(secret.get(), Some(&b"pass"[..]));
+
+        secret.set(b"pass", Duration::from_secs(0)).unwrap();
+        assert_eq!(secret.get(), None);
+        assert!(secret.buf.iter().all(|&b| b == 0));
+
+        assert!(secret.set(&[0u8; MAX_SECRET + 1], Duration::from_secs(60)).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use std::io;
+
+use super::parse_args;
+use super::super::config::Profile;
+
+pub const USAGE: &str = "
+Passphrase caching agent, password is kept in locked memory until
+timeout or lock, other commands ask it before prompt. The agent listen
+on a unix socket only the user can access, $EAKIO_AGENT_SOCK if set.
+Password is cached only after it decrypted a file, new passwords are
+always asked.
+
+Usage:
+    eakio agent start [--timeout=<secs>] [--foreground]
+    eakio agent lock
+    eakio agent stop
+    eakio agent (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+    --timeout=<secs>     Forget password after seconds [default: 900].
+    --foreground         Not run in background.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    cmd_start: bool,
+    cmd_lock: bool,
+    cmd_stop: bool,
+    flag_timeout: u64,
+    flag_foreground: bool,
+}
+
+#[cfg(unix)]
+pub fn execute(argv: Vec<String>, _profile: &Profile) -> io::Result<()> {
+    use std::time::Duration;
+
+    use agent::{self, Agent};
+
+    let args: Args = parse_args(USAGE, argv);
+
+    if args.cmd_lock {
+        agent::lock()?;
+        info!("Agent locked");
+    } else if args.cmd_stop {
+        agent::stop()?;
+        info!("Agent stopped");
+    } else if args.cmd_start {
+        let path = agent::socket_path();
+        // bind first, error shown before go background
+        let agent = Agent::bind(&path, Duration::from_secs(args.flag_timeout))?;
+        info!("Agent listening on {:?}", path);
+
+        if !args.flag_foreground {
+            daemonize()?;
+        }
+        agent.serve()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn execute(argv: Vec<String>, _profile: &Profile) -> io::Result<()> {
+    use util::io_error;
+
+    let _: Args = parse_args(USAGE, argv);
+    Err(io_error("agent only support unix"))
+}
+
+// fork and detach from terminal, parent exit at once
+#[cfg(unix)]
+fn daemonize() -> io::Result<()> {
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+    use std::process;
+
+    use libc;
+
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => {}
+        _ => process::exit(0),
+    }
+
+    if unsafe { libc::setsid() } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in 0..3 {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+use super::{cache_secret, input_password, opens_file, parse_args};
+use super::super::config::Profile;
+use super::super::file::FileCrypt;
+use super::super::util::{expand_tilde_path, io_error};
+
+pub const USAGE: &str = "
+Decrypt files and print them to stdout, nothing written to disk.
+
+Usage:
+    eakio cat <file>...
+    eakio cat (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_file: Vec<String>,
+}
+
+pub fn execute(argv: Vec<String>, profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+
+    // open all first, so password not asked for missing files
+    let mut files = Vec::with_capacity(args.arg_file.len());
+    for path in &args.arg_file {
+        let path = expand_tilde_path(path).into_owned();
+        let f = File::open(&path)?;
+        let size = f.metadata()?.len() as usize;
+        files.push((path, f, size));
+    }
+
+    let first = Path::new(&files[0].0);
+    let secret = input_password(profile, "Password", false, &|s| opens_file(s, first))?;
+    let mut file_crypt = FileCrypt::new(&secret);
+
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    for (path, f, size) in files {
+        file_crypt
+            .decrypt_stream(&mut BufReader::new(f), size, &mut writer)
+            .map_err(|e| io_error(&format!("cat '{}', {}", path, e)))?;
+    }
+    cache_secret(&secret);
+
+    writer.flush()
+}
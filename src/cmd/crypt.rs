@@ -0,0 +1,346 @@
+use std::io;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+
+use ring::signature::Ed25519KeyPair;
+
+use super::{cache_secret, input_secret, load_kms, opens_file, prompt_password, run_tasks};
+use super::super::config::Profile;
+use super::super::kms::KeyProvider;
+use super::super::manifest::{Manifest, Seal};
+use super::super::padding::Padding;
+use super::super::recovery;
+use super::super::sign;
+use super::super::scan::{
+    apply_extension, build_tasks, detect_hard_links, list_in_place_tasks, list_src_files,
+    prescan_encrypted, src_dirs, ListOptions, Prescan, Symlinks,
+};
+use super::super::task::{validate_tasks, Kind, Mode, Options, Task, TaskRuner};
+use super::super::util::io_error;
+
+// encrypt and decrypt share the same steps, flags not in one of
+// them are false
+#[derive(Debug, Default)]
+pub struct CryptArgs {
+    pub srcs: Vec<String>,
+    pub dest: String,
+    pub in_place: bool,
+    pub skip: bool,
+    pub overwrite: bool,
+    pub dry_run: bool,
+    pub hidden: bool,
+    pub follow_symlinks: bool,
+    pub store_symlinks: bool,
+    pub keep_dirs: bool,
+    pub keep_mode: bool,
+    pub no_ext: bool,
+    pub copy_plain: bool,
+    pub refuse_v1: bool,
+    pub excludes: Vec<String>,
+    pub includes: Vec<String>,
+    pub parallel: Option<i32>,
+    pub remove_source: bool,
+    pub shred: bool,
+    pub kms: Option<String>,
+    pub shares: Vec<String>,
+    // encrypt: add recovery code slot, decrypt: unlock by it
+    pub recovery_code: bool,
+    // encrypt only
+    pub sign: Option<String>,
+    pub manifest: Option<String>,
+    pub pad: Option<String>,
+    // decrypt only
+    pub trusted_signers: Vec<String>,
+}
+
+pub fn run(mode: Mode, args: &CryptArgs, profile: &Profile) -> io::Result<()> {
+    // no secret to authenticate manifest with kms, fail before run
+    if args.manifest.is_some() && args.sign.is_none() && profile.kms(&args.kms).is_some() {
+        return Err(io_error("manifest with --kms must be signed, need --sign"));
+    }
+    // followed links lead out of src, never remove or shred there
+    if args.remove_source && args.follow_symlinks {
+        return Err(io_error("--remove-source not work with --follow-symlinks"));
+    }
+    // copied plain files are signed by nobody
+    if args.copy_plain && !args.trusted_signers.is_empty() {
+        return Err(io_error("--copy-plain not work with --trusted-signer"));
+    }
+    if args.in_place {
+        return run_in_place(mode, args, profile);
+    }
+
+    let dest_is_dir = args.dest.ends_with(MAIN_SEPARATOR);
+    let dest = PathBuf::from(&args.dest);
+
+    let symlinks = if args.follow_symlinks {
+        Symlinks::Follow
+    } else if args.store_symlinks {
+        Symlinks::Store
+    } else {
+        Symlinks::Skip
+    };
+
+    let list_opts = ListOptions {
+        hidden: profile.hidden(args.hidden),
+        symlinks,
+        // dir records only make sense when encrypt
+        keep_dirs: mode == Mode::Encrypt && profile.keep_dirs(args.keep_dirs),
+    };
+
+    let mut filter = profile.filter(&args.excludes, &args.includes)?;
+    let files = list_src_files(&args.srcs, &list_opts, &mut filter)?;
+    let count: usize = files.iter().map(|pg| pg.subs.len()).sum();
+
+    info!("Found {} files to {}", count, mode);
+    if count == 0 {
+        return Ok(());
+    }
+    if count > 1 && !dest_is_dir {
+        return Err(io_error(&format!(
+            "multiple files dest must a dir, '{}' need endswith '{}'",
+            dest.display(),
+            MAIN_SEPARATOR
+        )));
+    }
+
+    let mut tasks = build_tasks(&files, &dest, dest_is_dir);
+    if let Mode::Decrypt = mode {
+        let scan = Prescan {
+            copy_plain: args.copy_plain,
+            refuse_v1: args.refuse_v1,
+            ..Default::default()
+        };
+        tasks = prescan_encrypted(tasks, mode, scan);
+    }
+    if dest_is_dir && !profile.no_ext(args.no_ext) {
+        apply_extension(&mut tasks, mode);
+    }
+    let mut tasks = validate_tasks(&src_dirs(&files), &dest, tasks)?;
+    detect_hard_links(&mut tasks);
+    check_manifest_files(mode, args, &tasks)?;
+
+    // hard links run after the files they link to, dirs run at last,
+    // deeper first, so dir mode not block writing
+    let (mut dirs, tasks): (Vec<Task>, Vec<Task>) =
+        tasks.into_iter().partition(|t| t.kind == Kind::Dir);
+    let (links, tasks): (Vec<Task>, Vec<Task>) = tasks
+        .into_iter()
+        .partition(|t| matches!(t.kind, Kind::HardLink(_)));
+    dirs.sort_by(|a, b| {
+        b.dest
+            .components()
+            .count()
+            .cmp(&a.dest.components().count())
+    });
+
+    // bad scheme fails before password asked
+    let padding = if mode == Mode::Encrypt {
+        profile.padding(&args.pad)?
+    } else {
+        Padding::None
+    };
+    let kms = load_kms(profile, &args.kms)?;
+    let secret = crypt_secret(mode, args, profile, &kms, &tasks)?;
+    let code = recovery_code(mode, args)?;
+    let signer = match args.sign {
+        Some(ref path) => Some(sign::load_key(path)?),
+        None => None,
+    };
+    let trusted = args.trusted_signers
+        .iter()
+        .map(|key| sign::read_public_key(key))
+        .collect::<io::Result<Vec<_>>>()?;
+    let (skip_exists, overwrite) = profile.exists(args.skip, args.overwrite);
+    let opts = Options {
+        skip_exists,
+        overwrite,
+        dry_run: args.dry_run,
+        remove_source: args.remove_source,
+        shred: args.shred,
+        keep_mode: args.keep_mode,
+        in_place: false,
+    };
+    let mut runer = TaskRuner::new(&secret, mode, opts);
+    if let Some(ref kms) = kms {
+        runer.set_kms(&**kms);
+    }
+    if let Some(ref code) = code {
+        runer.set_recovery(code.as_bytes());
+    }
+    if let Some(ref signer) = signer {
+        runer.set_signer(signer);
+    }
+    runer.set_trusted(&trusted);
+    runer.set_padding(padding);
+    if dest_is_dir {
+        runer.set_dest_root(&dest);
+    }
+    let parallel = profile.parallel(args.parallel);
+
+    let done = run_tasks(&mut runer, &tasks, parallel);
+    run_tasks(&mut runer, &links, parallel);
+    // dirs are small, one by one keep the order
+    let dirs_done = runer.simple_run(&dirs);
+    let decrypted = any_decrypted(&tasks, &done) || any_decrypted(&dirs, &dirs_done);
+    remember_secret(mode, args, &kms, &secret, decrypted);
+
+    write_manifest(&runer, args, &secret, &signer)
+}
+
+fn run_in_place(mode: Mode, args: &CryptArgs, profile: &Profile) -> io::Result<()> {
+    let mut filter = profile.filter(&args.excludes, &args.includes)?;
+    let mut tasks =
+        list_in_place_tasks(&args.srcs, profile.hidden(args.hidden), &mut filter, false)?;
+
+    if let Mode::Decrypt = mode {
+        let scan = Prescan {
+            refuse_v1: args.refuse_v1,
+            ..Default::default()
+        };
+        tasks = prescan_encrypted(tasks, mode, scan);
+    }
+    if !profile.no_ext(args.no_ext) {
+        apply_extension(&mut tasks, mode);
+    }
+
+    info!("Found {} files to {} in place", tasks.len(), mode);
+    if tasks.is_empty() {
+        return Ok(());
+    }
+    check_manifest_files(mode, args, &tasks)?;
+
+    // bad scheme fails before password asked
+    let padding = if mode == Mode::Encrypt {
+        profile.padding(&args.pad)?
+    } else {
+        Padding::None
+    };
+    let kms = load_kms(profile, &args.kms)?;
+    let secret = crypt_secret(mode, args, profile, &kms, &tasks)?;
+    let code = recovery_code(mode, args)?;
+    let signer = match args.sign {
+        Some(ref path) => Some(sign::load_key(path)?),
+        None => None,
+    };
+    let trusted = args.trusted_signers
+        .iter()
+        .map(|key| sign::read_public_key(key))
+        .collect::<io::Result<Vec<_>>>()?;
+    let (skip_exists, overwrite) = profile.exists(args.skip, args.overwrite);
+    let opts = Options {
+        skip_exists,
+        overwrite,
+        dry_run: args.dry_run,
+        in_place: true,
+        ..Default::default()
+    };
+    let mut runer = TaskRuner::new(&secret, mode, opts);
+    if let Some(ref kms) = kms {
+        runer.set_kms(&**kms);
+    }
+    if let Some(ref code) = code {
+        runer.set_recovery(code.as_bytes());
+    }
+    if let Some(ref signer) = signer {
+        runer.set_signer(signer);
+    }
+    runer.set_trusted(&trusted);
+    runer.set_padding(padding);
+    let done = run_tasks(&mut runer, &tasks, profile.parallel(args.parallel));
+    remember_secret(mode, args, &kms, &secret, any_decrypted(&tasks, &done));
+
+    write_manifest(&runer, args, &secret, &signer)
+}
+
+fn crypt_secret(
+    mode: Mode,
+    args: &CryptArgs,
+    profile: &Profile,
+    kms: &Option<Box<dyn KeyProvider>>,
+    tasks: &[Task],
+) -> io::Result<Vec<u8>> {
+    if mode == Mode::Decrypt && args.recovery_code {
+        return Ok(prompt_password("Recovery Code", false)?.into_bytes());
+    }
+
+    // typo of decrypt password is caught by key slots, password in agent
+    // checked by the first file to decrypt
+    let first = tasks.iter().find(|t| t.kind == Kind::File);
+    input_secret(profile, kms, &args.shares, mode == Mode::Encrypt, &|s| {
+        first.is_none_or(|t| opens_file(s, &t.src))
+    })
+}
+
+// any task really decrypted, copied plain files and hard links opened
+// no key slot
+fn any_decrypted(tasks: &[Task], done: &[bool]) -> bool {
+    tasks
+        .iter()
+        .zip(done)
+        .any(|(task, &ok)| ok && matches!(task.kind, Kind::File | Kind::Symlink | Kind::Dir))
+}
+
+// cache password after it decrypted a file, not kms, shares or code
+fn remember_secret(
+    mode: Mode,
+    args: &CryptArgs,
+    kms: &Option<Box<dyn KeyProvider>>,
+    secret: &[u8],
+    decrypted: bool,
+) {
+    let by_password = kms.is_none() && args.shares.is_empty() && !args.recovery_code;
+    if mode == Mode::Decrypt && by_password && decrypted {
+        cache_secret(secret);
+    }
+}
+
+// dest out of manifest dir fails before anything written
+fn check_manifest_files(mode: Mode, args: &CryptArgs, tasks: &[Task]) -> io::Result<()> {
+    match args.manifest {
+        Some(ref path) if mode == Mode::Encrypt => {
+            let dests: Vec<PathBuf> = tasks.iter().map(|t| t.dest.clone()).collect();
+            Manifest::check_files(Path::new(path), &dests)
+        }
+        _ => Ok(()),
+    }
+}
+
+// signed if signer given, or authenticated by secret
+fn write_manifest(
+    runer: &TaskRuner,
+    args: &CryptArgs,
+    secret: &[u8],
+    signer: &Option<Ed25519KeyPair>,
+) -> io::Result<()> {
+    let path = match args.manifest {
+        Some(ref path) if !args.dry_run => Path::new(path),
+        _ => return Ok(()),
+    };
+
+    let seal = match *signer {
+        Some(ref signer) => Seal::Sign(signer),
+        None => Seal::Mac(secret),
+    };
+    let count = runer.write_manifest(path, &seal)?;
+    info!("Wrote manifest {:?} of {} files", path, count);
+
+    Ok(())
+}
+
+// new code for this run, shown only once, nothing if dry run
+fn recovery_code(mode: Mode, args: &CryptArgs) -> io::Result<Option<String>> {
+    if mode != Mode::Encrypt || !args.recovery_code || args.dry_run {
+        return Ok(None);
+    }
+
+    let code = recovery::generate()?;
+    let groups: Vec<&str> = code.split('-').collect();
+    let half = groups.len() / 2;
+    println!("Recovery code, shown only once, print it and keep it safe:");
+    println!();
+    println!("    {}", groups[..half].join("-"));
+    println!("    {}", groups[half..].join("-"));
+    println!();
+
+    Ok(Some(code))
+}
@@ -0,0 +1,88 @@
+use std::io;
+
+use super::crypt::{self, CryptArgs};
+use super::parse_args;
+use super::super::config::Profile;
+use super::super::task::Mode;
+
+pub const USAGE: &str = "
+Decrypt files or dirs to dest, or replace them in place.
+
+Usage:
+    eakio decrypt <src>... <dest> [-n] [--skip | --overwrite] [--hidden] [--follow-symlinks] [--no-ext] [--copy-plain] [--refuse-v1] [--exclude=<pattern>...] [--include=<pattern>...] [--parallel=<N>] [--kms=<uri> | --share=<file>... | --recovery-code] [--trusted-signer=<pubkey>...]
+    eakio decrypt --in-place <src>... [-n] [--skip | --overwrite] [--hidden] [--no-ext] [--refuse-v1] [--exclude=<pattern>...] [--include=<pattern>...] [--parallel=<N>] [--kms=<uri> | --share=<file>... | --recovery-code] [--trusted-signer=<pubkey>...]
+    eakio decrypt (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+    -n --dryrun          Only show what should be do.
+    --skip               Skip exists dest file.
+    --overwrite          Overwrite exists dest file.
+    --hidden             Include hidden files.
+    --follow-symlinks    Follow symlinks in src dir.
+    --no-ext             Not remove '.eak' extension of dest.
+    --copy-plain         Copy not encrypted files unchanged, not with
+                         --trusted-signer.
+    --refuse-v1          Refuse legacy version 1 files.
+    --exclude=<pattern>  Exclude files match pattern.
+    --include=<pattern>  Only include files match pattern.
+    --in-place           Replace src file with its decrypted file.
+    --parallel=<N>       Parallel run, -1 use cpu count.
+    --kms=<uri>          Unwrap data keys by key service, not password.
+    --share=<file>       Share of key from split-key, in file or asked
+                         if '-', key combined from them not password.
+    --recovery-code      Ask recovery code, not password.
+    --trusted-signer=<pubkey>
+                         Only decrypt files signed by one of them, public
+                         key from keygen, or file of it.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_src: Vec<String>,
+    arg_dest: String,
+    flag_dryrun: bool,
+    flag_skip: bool,
+    flag_overwrite: bool,
+    flag_hidden: bool,
+    flag_follow_symlinks: bool,
+    flag_no_ext: bool,
+    flag_copy_plain: bool,
+    flag_refuse_v1: bool,
+    flag_exclude: Vec<String>,
+    flag_include: Vec<String>,
+    flag_in_place: bool,
+    flag_parallel: Option<i32>,
+    flag_kms: Option<String>,
+    flag_share: Vec<String>,
+    flag_recovery_code: bool,
+    flag_trusted_signer: Vec<String>,
+}
+
+pub fn execute(argv: Vec<String>, profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+
+    let crypt_args = CryptArgs {
+        srcs: args.arg_src,
+        dest: args.arg_dest,
+        in_place: args.flag_in_place,
+        skip: args.flag_skip,
+        overwrite: args.flag_overwrite,
+        dry_run: args.flag_dryrun,
+        hidden: args.flag_hidden,
+        follow_symlinks: args.flag_follow_symlinks,
+        no_ext: args.flag_no_ext,
+        copy_plain: args.flag_copy_plain,
+        refuse_v1: args.flag_refuse_v1,
+        excludes: args.flag_exclude,
+        includes: args.flag_include,
+        parallel: args.flag_parallel,
+        kms: args.flag_kms,
+        shares: args.flag_share,
+        recovery_code: args.flag_recovery_code,
+        trusted_signers: args.flag_trusted_signer,
+        ..Default::default()
+    };
+
+    crypt::run(Mode::Decrypt, &crypt_args, profile)
+}
@@ -0,0 +1,201 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use ring::digest;
+use time;
+
+use super::{cache_secret, check_reencrypt, input_password, opens_file, parse_args};
+use super::super::config::Profile;
+use super::super::file::{probe_version, FileCrypt};
+use super::super::task::tmp_path;
+use super::super::util::{
+    create_private, create_private_dir, expand_tilde_path, io_error, remove_file_secure, sync_dir,
+    sync_file,
+};
+
+pub const USAGE: &str = "
+Decrypt file to a private temp file, open it in $EDITOR, encrypt it
+back if changed. The temp file is in a private dir, shredded after
+edit.
+
+Usage:
+    eakio edit <file>
+    eakio edit (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_file: String,
+}
+
+pub fn execute(argv: Vec<String>, profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+    let path = PathBuf::from(&*expand_tilde_path(&args.arg_file));
+
+    if probe_version(&path)?.is_none() {
+        return Err(io_error(&format!("{:?} is not encrypted", path)));
+    }
+
+    let secret = input_password(profile, "Password", false, &|s| opens_file(s, &path))?;
+    let mut file_crypt = FileCrypt::new(&secret);
+    // before edit, not lose the changes
+    check_reencrypt(&mut file_crypt, &path)?;
+
+    let plain = PlainFile::create(&path)?;
+    {
+        let src_f = File::open(&path)?;
+        let size = src_f.metadata()?.len() as usize;
+        let mut writer = BufWriter::new(plain.open()?);
+        file_crypt.decrypt_stream(&mut BufReader::new(src_f), size, &mut writer)?;
+        writer.flush()?;
+    }
+    cache_secret(&secret);
+    let before = plain.digest()?;
+
+    run_editor(&plain.path)?;
+
+    if plain.digest()? == before {
+        info!("{:?} not changed", path);
+        return Ok(());
+    }
+
+    // new salt every encrypt, write aside then rename
+    let tmp = tmp_path(&path);
+    let result = encrypt_to(&mut file_crypt, &plain.path, &tmp, &path)
+        .and_then(|_| fs::rename(&tmp, &path))
+        .and_then(|_| sync_dir(&path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result?;
+
+    info!("{:?} updated", path);
+    Ok(())
+}
+
+fn encrypt_to(file_crypt: &mut FileCrypt, plain: &Path, tmp: &Path, orig: &Path) -> io::Result<()> {
+    file_crypt.encrypt(plain, tmp)?;
+    fs::set_permissions(tmp, fs::metadata(orig)?.permissions())?;
+    sync_file(tmp)
+}
+
+fn run_editor(path: &Path) -> io::Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_owned());
+
+    let status = editor_command(&editor, path)
+        .status()
+        .map_err(|e| io_error(&format!("run editor '{}', {}", editor, e)))?;
+    if !status.success() {
+        return Err(io_error(&format!(
+            "editor '{}' exit with {}",
+            editor, status
+        )));
+    }
+
+    Ok(())
+}
+
+// editor may have args, like 'code -w'
+#[cfg(unix)]
+fn editor_command(editor: &str, path: &Path) -> Command {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path);
+    command
+}
+
+#[cfg(not(unix))]
+fn editor_command(editor: &str, path: &Path) -> Command {
+    let mut command = Command::new(editor);
+    command.arg(path);
+    command
+}
+
+// decrypted temp file, only owner can read, shredded when dropped
+// plain file in its own private dir, swap and backup files of editor
+// are kept in it too, all removed at last
+struct PlainFile {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl PlainFile {
+    fn create(orig: &Path) -> io::Result<PlainFile> {
+        // keep the file name, so editor can detect file type
+        let name = orig
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let name = if name.is_empty() { "plain".to_string() } else { name };
+        let dir = private_tmp_dir().join(format!(
+            "eakio-{}-{}",
+            process::id(),
+            time::precise_time_ns()
+        ));
+        create_private_dir(&dir)?;
+
+        let plain = PlainFile {
+            path: dir.join(name),
+            dir,
+        };
+        create_private(&plain.path)?;
+        Ok(plain)
+    }
+
+    fn open(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&self.path)
+    }
+
+    fn digest(&self) -> io::Result<Vec<u8>> {
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        let mut f = File::open(&self.path)?;
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = f.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            ctx.update(&buf[..n]);
+        }
+
+        Ok(ctx.finish().as_ref().to_vec())
+    }
+}
+
+impl Drop for PlainFile {
+    fn drop(&mut self) {
+        if self.path.exists() {
+            if let Err(e) = remove_file_secure(&self.path, true) {
+                error!("remove temp file {:?} ({})", self.path, e);
+            }
+        }
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            error!("remove temp dir {:?} ({})", self.dir, e);
+        }
+    }
+}
+
+// memory backed tmpfs if there is, plain data never hit the disk
+fn private_tmp_dir() -> PathBuf {
+    let shm = Path::new("/dev/shm");
+    if cfg!(target_os = "linux") && shm.is_dir() {
+        shm.to_path_buf()
+    } else {
+        env::temp_dir()
+    }
+}
@@ -0,0 +1,109 @@
+use std::io;
+
+use super::crypt::{self, CryptArgs};
+use super::parse_args;
+use super::super::config::Profile;
+use super::super::task::Mode;
+
+pub const USAGE: &str = "
+Encrypt files or dirs to dest, or replace them in place.
+
+Usage:
+    eakio encrypt <src>... <dest> [-n] [--skip | --overwrite] [--hidden] [--follow-symlinks | --store-symlinks] [--keep-dirs [--keep-mode]] [--no-ext] [--exclude=<pattern>...] [--include=<pattern>...] [--parallel=<N>] [--kms=<uri> | --share=<file>...] [--recovery-code] [--sign=<key>] [--manifest=<file>] [--pad=<scheme>] [--remove-source [--shred]]
+    eakio encrypt --in-place <src>... [-n] [--skip | --overwrite] [--hidden] [--no-ext] [--exclude=<pattern>...] [--include=<pattern>...] [--parallel=<N>] [--kms=<uri> | --share=<file>...] [--recovery-code] [--sign=<key>] [--manifest=<file>] [--pad=<scheme>]
+    eakio encrypt (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+    -n --dryrun          Only show what should be do.
+    --skip               Skip exists dest file.
+    --overwrite          Overwrite exists dest file.
+    --hidden             Include hidden files.
+    --follow-symlinks    Follow symlinks in src dir.
+    --store-symlinks     Encrypt symlinks as link records, not follow them.
+    --keep-dirs          Encrypt dirs as dir records, keep empty dirs.
+    --keep-mode          Keep dir mode in dir records.
+    --no-ext             Not append '.eak' extension to dest.
+    --exclude=<pattern>  Exclude files match pattern.
+    --include=<pattern>  Only include files match pattern.
+    --remove-source      Remove src file after encrypted file verified,
+                         not with --follow-symlinks.
+    --shred              Overwrite src file before remove.
+    --in-place           Replace src file with its encrypted file.
+    --parallel=<N>       Parallel run, -1 use cpu count.
+    --kms=<uri>          Wrap data keys by key service, not password,
+                         like vault://<host>/<mount>/<key>, token
+                         from $VAULT_TOKEN.
+    --share=<file>       Share of key from split-key, in file or asked
+                         if '-', key combined from them not password.
+    --recovery-code      Generate a recovery code shown only once, it
+                         unlocks the files too if password is lost.
+    --sign=<key>         Sign files by private key from keygen.
+    --manifest=<file>    Write manifest of encrypted files with their
+                         sizes and header hashes, signed if --sign or
+                         authenticated by password. Files must be in
+                         its dir, check it by 'verify --manifest'.
+    --pad=<scheme>       Pad files so sizes leak less, padme (at most
+                         12% larger), block:<size> (round up to it,
+                         like block:4k) or none.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_src: Vec<String>,
+    arg_dest: String,
+    flag_dryrun: bool,
+    flag_skip: bool,
+    flag_overwrite: bool,
+    flag_hidden: bool,
+    flag_follow_symlinks: bool,
+    flag_store_symlinks: bool,
+    flag_keep_dirs: bool,
+    flag_keep_mode: bool,
+    flag_no_ext: bool,
+    flag_exclude: Vec<String>,
+    flag_include: Vec<String>,
+    flag_remove_source: bool,
+    flag_shred: bool,
+    flag_in_place: bool,
+    flag_parallel: Option<i32>,
+    flag_kms: Option<String>,
+    flag_share: Vec<String>,
+    flag_recovery_code: bool,
+    flag_sign: Option<String>,
+    flag_manifest: Option<String>,
+    flag_pad: Option<String>,
+}
+
+pub fn execute(argv: Vec<String>, profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+
+    let crypt_args = CryptArgs {
+        srcs: args.arg_src,
+        dest: args.arg_dest,
+        in_place: args.flag_in_place,
+        skip: args.flag_skip,
+        overwrite: args.flag_overwrite,
+        dry_run: args.flag_dryrun,
+        hidden: args.flag_hidden,
+        follow_symlinks: args.flag_follow_symlinks,
+        store_symlinks: args.flag_store_symlinks,
+        keep_dirs: args.flag_keep_dirs,
+        keep_mode: args.flag_keep_mode,
+        no_ext: args.flag_no_ext,
+        excludes: args.flag_exclude,
+        includes: args.flag_include,
+        parallel: args.flag_parallel,
+        remove_source: args.flag_remove_source,
+        shred: args.flag_shred,
+        kms: args.flag_kms,
+        shares: args.flag_share,
+        recovery_code: args.flag_recovery_code,
+        sign: args.flag_sign,
+        manifest: args.flag_manifest,
+        pad: args.flag_pad,
+        ..Default::default()
+    };
+
+    crypt::run(Mode::Encrypt, &crypt_args, profile)
+}
@@ -0,0 +1,132 @@
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter, Cursor};
+use std::path::{Path, PathBuf};
+
+use super::{
+    cache_secret, check_reencrypt, input_password, opens_file, parse_args, prompt_password,
+};
+use super::super::config::Profile;
+use super::super::dotenv::EnvFile;
+use super::super::file::FileCrypt;
+use super::super::task::tmp_path;
+use super::super::util::{create_private, expand_tilde_path, io_error, sync_dir, sync_file};
+
+pub const USAGE: &str = "
+Edit keys in encrypted env file (dotenv format), plain data only in
+memory. If <value> not given, it will be asked without echo.
+
+Usage:
+    eakio env set <file> <key> [<value>]
+    eakio env get <file> <key>
+    eakio env unset <file> <key>
+    eakio env list <file>
+    eakio env (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    cmd_set: bool,
+    cmd_get: bool,
+    cmd_unset: bool,
+    cmd_list: bool,
+    arg_file: String,
+    arg_key: String,
+    arg_value: Option<String>,
+}
+
+pub fn execute(argv: Vec<String>, profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+    let path = PathBuf::from(&*expand_tilde_path(&args.arg_file));
+
+    // set can create new file
+    let create = args.cmd_set && !path.exists();
+    let secret = input_password(profile, "Password", create, &|s| opens_file(s, &path))?;
+    let mut file_crypt = FileCrypt::new(&secret);
+
+    let mut env = if create {
+        EnvFile::default()
+    } else {
+        let env = load_env_file(&mut file_crypt, &path)?;
+        cache_secret(&secret);
+        if args.cmd_set || args.cmd_unset {
+            check_reencrypt(&mut file_crypt, &path)?;
+        }
+        env
+    };
+
+    if args.cmd_get {
+        match env.get(&args.arg_key) {
+            Some(value) => println!("{}", value),
+            None => return Err(io_error(&format!("key '{}' not found", args.arg_key))),
+        }
+    } else if args.cmd_list {
+        // only keys, values are secret
+        for (key, _) in env.vars() {
+            println!("{}", key);
+        }
+    } else if args.cmd_set {
+        let value = match args.arg_value {
+            Some(ref value) => value.clone(),
+            None => prompt_password(&args.arg_key, false)?,
+        };
+        env.set(&args.arg_key, &value)?;
+        save_env_file(&mut file_crypt, &env, &path)?;
+        info!("{:?} set '{}'", path, args.arg_key);
+    } else if args.cmd_unset {
+        if !env.unset(&args.arg_key) {
+            return Err(io_error(&format!("key '{}' not found", args.arg_key)));
+        }
+        save_env_file(&mut file_crypt, &env, &path)?;
+        info!("{:?} unset '{}'", path, args.arg_key);
+    }
+
+    Ok(())
+}
+
+/// decrypt env file in memory and parse it
+pub fn load_env_file(file_crypt: &mut FileCrypt, path: &Path) -> io::Result<EnvFile> {
+    let f = File::open(path)?;
+    let size = f.metadata()?.len() as usize;
+
+    let mut data = Vec::new();
+    file_crypt
+        .decrypt_stream(&mut BufReader::new(f), size, &mut data)
+        .map_err(|e| io_error(&format!("decrypt env file {:?}, {}", path, e)))?;
+
+    let content =
+        String::from_utf8(data).map_err(|_| io_error(&format!("env file {:?} not utf-8", path)))?;
+    EnvFile::parse(&content)
+}
+
+// encrypt aside then rename, new file only owner can read
+fn save_env_file(file_crypt: &mut FileCrypt, env: &EnvFile, path: &Path) -> io::Result<()> {
+    let data = env.to_string().into_bytes();
+    let tmp = tmp_path(path);
+    // left by a crashed run, new one is created exclusively
+    let _ = fs::remove_file(&tmp);
+
+    let result = write_tmp(file_crypt, &data, &tmp, path)
+        .and_then(|_| fs::rename(&tmp, path))
+        .and_then(|_| sync_dir(path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn write_tmp(file_crypt: &mut FileCrypt, data: &[u8], tmp: &Path, path: &Path) -> io::Result<()> {
+    {
+        let mut writer = BufWriter::new(create_private(tmp)?);
+        file_crypt.encrypt_stream(&mut Cursor::new(data), data.len(), &mut writer)?;
+        writer.flush()?;
+    }
+
+    if let Ok(meta) = fs::metadata(path) {
+        fs::set_permissions(tmp, meta.permissions())?;
+    }
+    sync_file(tmp)
+}
@@ -0,0 +1,80 @@
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+use docopt::Docopt;
+
+use super::env::load_env_file;
+use super::{cache_secret, input_password, opens_file};
+use super::super::config::Profile;
+use super::super::file::FileCrypt;
+use super::super::util::{expand_tilde_path, io_error};
+
+pub const USAGE: &str = "
+Run command with variables in encrypted env files (dotenv format), env
+files are decrypted in memory, later file override former.
+
+Usage:
+    eakio exec --env-file=<file>... [--] <command> [<args>...]
+    eakio exec (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+    --env-file=<file>    Encrypted env file.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_env_file: Vec<String>,
+    arg_command: String,
+    arg_args: Vec<String>,
+}
+
+pub fn execute(argv: Vec<String>, profile: &Profile) -> io::Result<()> {
+    // args after command are for the command, not parsed
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.argv(argv).options_first(true).deserialize())
+        .unwrap_or_else(|e| e.exit());
+
+    let env_files: Vec<PathBuf> = args.flag_env_file
+        .iter()
+        .map(|path| PathBuf::from(&*expand_tilde_path(path)))
+        .collect();
+    let secret = input_password(profile, "Password", false, &|s| {
+        env_files.first().is_none_or(|path| opens_file(s, path))
+    })?;
+    let mut file_crypt = FileCrypt::new(&secret);
+
+    let mut command = Command::new(&args.arg_command);
+    command.args(&args.arg_args);
+    for path in &env_files {
+        let env = load_env_file(&mut file_crypt, path)?;
+        for (key, value) in env.vars() {
+            command.env(key, value);
+        }
+    }
+    if !env_files.is_empty() {
+        cache_secret(&secret);
+    }
+
+    run(command, &args.arg_command)
+}
+
+// replace current process, so signals and exit code go to command directly
+#[cfg(unix)]
+fn run(mut command: Command, name: &str) -> io::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let e = command.exec();
+    Err(io_error(&format!("exec '{}', {}", name, e)))
+}
+
+#[cfg(not(unix))]
+fn run(mut command: Command, name: &str) -> io::Result<()> {
+    use std::process;
+
+    let status = command
+        .status()
+        .map_err(|e| io_error(&format!("exec '{}', {}", name, e)))?;
+    process::exit(status.code().unwrap_or(1))
+}
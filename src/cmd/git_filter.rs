@@ -0,0 +1,83 @@
+use std::io::prelude::*;
+use std::io::{self, Cursor};
+
+use super::parse_args;
+use super::super::config::Profile;
+use super::super::file::{is_supported_version, probe_data_version, FileCrypt};
+use super::super::util::io_error;
+
+pub const USAGE: &str = "
+Git clean and smudge filter, read stdin and write stdout. Setup it
+with 'eakio git-init', password must come from config source.
+
+Usage:
+    eakio git-filter clean [--deterministic]
+    eakio git-filter smudge
+    eakio git-filter (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+    --deterministic      Salt derived from secret and content, unchanged
+                         file always get the same encrypted data.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    cmd_clean: bool,
+    cmd_smudge: bool,
+    flag_deterministic: bool,
+}
+
+pub fn execute(argv: Vec<String>, profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+
+    // stdin is file data, can not ask password
+    let secret = match profile.read_password()? {
+        Some(pass) => pass.into_bytes(),
+        None => {
+            return Err(io_error(
+                "git-filter need password_file, password_command or password_env in config",
+            ))
+        }
+    };
+    let mut file_crypt = FileCrypt::new(&secret);
+
+    let mut data = Vec::new();
+    io::stdin().read_to_end(&mut data)?;
+
+    let stdout = io::stdout();
+    let mut writer = stdout.lock();
+    let version = probe_data_version(&data);
+
+    if args.cmd_clean {
+        // already encrypted, not twice. plain data may start like the
+        // header, only pass it if it can be decrypted
+        if version.is_some() && is_encrypted(&mut file_crypt, &data) {
+            writer.write_all(&data)?;
+        } else if args.flag_deterministic {
+            file_crypt.encrypt_deterministic(&data, &mut writer)?;
+        } else {
+            file_crypt.encrypt_stream(&mut Cursor::new(&data), data.len(), &mut writer)?;
+        }
+    } else if args.cmd_smudge {
+        match version {
+            Some(v) if is_supported_version(v) => {
+                // decrypt to memory, broken data not go to working copy
+                let mut plain = Vec::new();
+                file_crypt.decrypt_stream(&mut Cursor::new(&data), data.len(), &mut plain)?;
+                writer.write_all(&plain)?;
+            }
+            Some(v) => return Err(io_error(&format!("version '{}' not support", v))),
+            // committed before filter setup
+            None => writer.write_all(&data)?,
+        }
+    }
+
+    writer.flush()
+}
+
+fn is_encrypted(file_crypt: &mut FileCrypt, data: &[u8]) -> bool {
+    file_crypt
+        .decrypt_stream(&mut Cursor::new(data), data.len(), &mut io::sink())
+        .is_ok()
+}
@@ -0,0 +1,146 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::parse_args;
+use super::super::config::Profile;
+use super::super::util::io_error;
+
+pub const USAGE: &str = "
+Setup eakio git filter in current repository, files match patterns are
+encrypted in git and decrypted in working copy. The filter read password
+from config source, so set password_file, password_command or
+password_env in config (or the profile) first.
+
+Usage:
+    eakio git-init <pattern>... [--config=<path>] [--profile=<name>]
+    eakio git-init (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+    --config=<path>      Config file used by filter.
+    --profile=<name>     Profile used by filter, need a password source.
+";
+
+const ATTRIBUTES: &str = ".gitattributes";
+const FILTER: &str = "eakio";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_pattern: Vec<String>,
+    flag_config: Option<String>,
+    flag_profile: Option<String>,
+}
+
+pub fn execute(argv: Vec<String>, _profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+    let root = git_toplevel()?;
+
+    // git runs filter by shell
+    let mut eakio = "eakio".to_owned();
+    if let Some(ref path) = args.flag_config {
+        eakio.push_str(&format!(" --config={}", shell_quote(path)?));
+    }
+    if let Some(ref name) = args.flag_profile {
+        eakio.push_str(&format!(" --profile={}", shell_quote(name)?));
+    }
+
+    let configs = [
+        (
+            format!("filter.{}.clean", FILTER),
+            format!("{} git-filter clean --deterministic", eakio),
+        ),
+        (
+            format!("filter.{}.smudge", FILTER),
+            format!("{} git-filter smudge", eakio),
+        ),
+        (format!("filter.{}.required", FILTER), "true".to_owned()),
+        // git diff show decrypted content
+        (
+            format!("diff.{}.textconv", FILTER),
+            format!("{} cat", eakio),
+        ),
+    ];
+    for (key, value) in &configs {
+        git_config(key, value)?;
+    }
+
+    let attrs: Vec<String> = args
+        .arg_pattern
+        .iter()
+        .map(|p| format!("{} filter={} diff={}", p, FILTER, FILTER))
+        .collect();
+    let path = root.join(ATTRIBUTES);
+    let added = append_attributes(&path, &attrs)?;
+    info!("{} patterns added to {:?}", added, path);
+
+    Ok(())
+}
+
+// single quoted, quote in it closed, escaped and reopened. newline
+// breaks git config, refused
+fn shell_quote(value: &str) -> io::Result<String> {
+    if value.contains('\n') || value.contains('\r') {
+        return Err(io_error(&format!("{:?} has newline, not supported", value)));
+    }
+
+    Ok(format!("'{}'", value.replace('\'', "'\\''")))
+}
+
+fn git_toplevel() -> io::Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .map_err(|e| io_error(&format!("run git, {}", e)))?;
+    if !output.status.success() {
+        return Err(io_error("not in a git repository"));
+    }
+
+    let root = String::from_utf8_lossy(&output.stdout);
+    Ok(PathBuf::from(root.trim_end()))
+}
+
+fn git_config(key: &str, value: &str) -> io::Result<()> {
+    let status = Command::new("git")
+        .args(["config", key, value])
+        .status()
+        .map_err(|e| io_error(&format!("run git, {}", e)))?;
+    if !status.success() {
+        return Err(io_error(&format!("git config {} failed, {}", key, status)));
+    }
+
+    info!("git config {} '{}'", key, value);
+    Ok(())
+}
+
+// only lines not exists are appended, returns count of them
+fn append_attributes(path: &Path, lines: &[String]) -> io::Result<usize> {
+    let mut content = String::new();
+    if path.is_file() {
+        File::open(path)?.read_to_string(&mut content)?;
+    }
+
+    let mut append = String::new();
+    if !content.is_empty() && !content.ends_with('\n') {
+        append.push('\n');
+    }
+
+    let mut count = 0;
+    for line in lines {
+        if content.lines().any(|l| l.trim() == line) {
+            continue;
+        }
+        append.push_str(line);
+        append.push('\n');
+        count += 1;
+    }
+
+    if count > 0 {
+        let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+        f.write_all(append.as_bytes())?;
+    }
+
+    Ok(count)
+}
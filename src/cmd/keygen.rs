@@ -0,0 +1,51 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use base64;
+
+use super::parse_args;
+use super::super::config::Profile;
+use super::super::sign;
+use super::super::util::{create_private, expand_tilde_path};
+
+pub const USAGE: &str = "
+Generate an Ed25519 signing key for 'encrypt --sign'. Private key is
+written to <file>, only you can read it, public key to <file>.pub and
+stdout, give it to the ones checking your files by '--trusted-signer'.
+
+Usage:
+    eakio keygen <file>
+    eakio keygen (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_file: String,
+}
+
+pub fn execute(argv: Vec<String>, _profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+    let path = PathBuf::from(&*expand_tilde_path(&args.arg_file));
+    let mut pub_path = path.clone().into_os_string();
+    pub_path.push(".pub");
+
+    let (pkcs8, public_key) = sign::generate()?;
+    let public_key = base64::encode(&public_key);
+
+    // never overwrite an old key
+    create_private(&path)?.write_all(base64::encode(&pkcs8).as_bytes())?;
+    File::create(&pub_path)?.write_all(format!("{}\n", public_key).as_bytes())?;
+
+    println!("{}", public_key);
+    info!(
+        "Signing key written to {:?}, public key to {:?}",
+        path, pub_path
+    );
+
+    Ok(())
+}
@@ -0,0 +1,322 @@
+use std::fs;
+use std::io;
+use std::path::{Path, MAIN_SEPARATOR};
+
+use docopt::Docopt;
+use rpassword;
+use serde::de::DeserializeOwned;
+
+use super::config::Profile;
+use super::file::FileCrypt;
+use super::kms::{self, KeyProvider};
+use super::shamir::{self, Share};
+use super::task::{Task, TaskRuner};
+use super::util::io_error;
+
+mod agent;
+mod cat;
+mod crypt;
+mod decrypt;
+mod edit;
+mod encrypt;
+mod env;
+mod exec;
+mod git_filter;
+mod git_init;
+mod keygen;
+mod rekey;
+mod split_key;
+mod sync;
+mod upgrade;
+mod verify;
+mod watch;
+
+// every command has its own usage, run with args after command name
+struct Command {
+    name: &'static str,
+    about: &'static str,
+    usage: &'static str,
+    execute: fn(Vec<String>, &Profile) -> io::Result<()>,
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "encrypt",
+        about: "Encrypt files or dirs",
+        usage: encrypt::USAGE,
+        execute: encrypt::execute,
+    },
+    Command {
+        name: "decrypt",
+        about: "Decrypt files or dirs",
+        usage: decrypt::USAGE,
+        execute: decrypt::execute,
+    },
+    Command {
+        name: "agent",
+        about: "Passphrase caching agent",
+        usage: agent::USAGE,
+        execute: agent::execute,
+    },
+    Command {
+        name: "cat",
+        about: "Decrypt files to stdout",
+        usage: cat::USAGE,
+        execute: cat::execute,
+    },
+    Command {
+        name: "edit",
+        about: "Edit encrypted file in $EDITOR",
+        usage: edit::USAGE,
+        execute: edit::execute,
+    },
+    Command {
+        name: "env",
+        about: "Edit keys in encrypted env file",
+        usage: env::USAGE,
+        execute: env::execute,
+    },
+    Command {
+        name: "exec",
+        about: "Run command with encrypted env files",
+        usage: exec::USAGE,
+        execute: exec::execute,
+    },
+    Command {
+        name: "git-filter",
+        about: "Git clean and smudge filter",
+        usage: git_filter::USAGE,
+        execute: git_filter::execute,
+    },
+    Command {
+        name: "git-init",
+        about: "Setup git filter in repository",
+        usage: git_init::USAGE,
+        execute: git_init::execute,
+    },
+    Command {
+        name: "keygen",
+        about: "Generate a signing key",
+        usage: keygen::USAGE,
+        execute: keygen::execute,
+    },
+    Command {
+        name: "rekey",
+        about: "Re-encrypt files with a new password",
+        usage: rekey::USAGE,
+        execute: rekey::execute,
+    },
+    Command {
+        name: "upgrade",
+        about: "Rewrite old version files to latest version",
+        usage: upgrade::USAGE,
+        execute: upgrade::execute,
+    },
+    Command {
+        name: "split-key",
+        about: "Split a new key into shares",
+        usage: split_key::USAGE,
+        execute: split_key::execute,
+    },
+    Command {
+        name: "sync",
+        about: "Encrypt new or changed files to dest dir",
+        usage: sync::USAGE,
+        execute: sync::execute,
+    },
+    Command {
+        name: "verify",
+        about: "Check signatures of encrypted files",
+        usage: verify::USAGE,
+        execute: verify::execute,
+    },
+    Command {
+        name: "watch",
+        about: "Watch dir and encrypt files when written",
+        usage: watch::USAGE,
+        execute: watch::execute,
+    },
+];
+
+fn find(name: &str) -> io::Result<&'static Command> {
+    COMMANDS.iter().find(|c| c.name == name).ok_or_else(|| {
+        io_error(&format!(
+            "no such command '{}', see 'eakio --help'",
+            name
+        ))
+    })
+}
+
+/// commands list shown in top level help
+pub fn list() -> String {
+    COMMANDS
+        .iter()
+        .map(|c| format!("    {:<11}{}", c.name, c.about))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn run(name: &str, args: &[String], profile: &Profile) -> io::Result<()> {
+    let cmd = find(name)?;
+
+    // docopt skip the first, the program name
+    let mut argv = vec!["eakio".to_owned(), name.to_owned()];
+    argv.extend(args.iter().cloned());
+
+    (cmd.execute)(argv, profile)
+}
+
+pub fn help(name: &str) -> io::Result<()> {
+    print!("{}", find(name)?.usage);
+    Ok(())
+}
+
+fn parse_args<T: DeserializeOwned>(usage: &str, argv: Vec<String>) -> T {
+    Docopt::new(usage)
+        .and_then(|d| d.argv(argv).deserialize())
+        .unwrap_or_else(|e| e.exit())
+}
+
+fn check_dest_dir(cmd: &str, dest: &str) -> io::Result<()> {
+    if dest.ends_with(MAIN_SEPARATOR) {
+        return Ok(());
+    }
+
+    Err(io_error(&format!(
+        "{} dest must a dir, '{}' need endswith '{}'",
+        cmd, dest, MAIN_SEPARATOR
+    )))
+}
+
+fn run_tasks(runer: &mut TaskRuner, tasks: &[Task], parallel: Option<i32>) -> Vec<bool> {
+    let parallel = parallel.unwrap_or(0);
+    if parallel == 0 {
+        runer.simple_run(tasks)
+    } else {
+        runer.parallel_run(tasks, parallel)
+    }
+}
+
+// password from config source, or agent, or ask it. a new password
+// (confirm) is always asked, never taken from agent. password in agent
+// maybe an old one, asked too if check not accept it. asked password is
+// not cached here, see cache_secret
+fn input_password(
+    profile: &Profile,
+    name: &str,
+    confirm: bool,
+    check: &dyn Fn(&[u8]) -> bool,
+) -> io::Result<Vec<u8>> {
+    if let Some(pass) = profile.read_password()? {
+        return Ok(pass.into_bytes());
+    }
+    if !confirm {
+        if let Some(secret) = cached_secret() {
+            if check(&secret) {
+                return Ok(secret);
+            }
+            warn!("Password in agent not work");
+        }
+    }
+
+    Ok(prompt_password(name, confirm)?.into_bytes())
+}
+
+// key service from flag or config
+fn load_kms(profile: &Profile, flag: &Option<String>) -> io::Result<Option<Box<dyn KeyProvider>>> {
+    match profile.kms(flag) {
+        Some(uri) => kms::from_uri(&uri).map(Some),
+        None => Ok(None),
+    }
+}
+
+// no password needed if data keys are wrapped by kms, or key is
+// combined from shares
+fn input_secret(
+    profile: &Profile,
+    kms: &Option<Box<dyn KeyProvider>>,
+    shares: &[String],
+    confirm: bool,
+    check: &dyn Fn(&[u8]) -> bool,
+) -> io::Result<Vec<u8>> {
+    if kms.is_some() {
+        Ok(Vec::new())
+    } else if !shares.is_empty() {
+        combine_shares(shares)
+    } else {
+        input_password(profile, "Password", confirm, check)
+    }
+}
+
+// check of input_password, secret opens the encrypted file
+fn opens_file(secret: &[u8], path: &Path) -> bool {
+    FileCrypt::new(secret).can_open(path)
+}
+
+// every share in a file, or asked if '-'
+fn combine_shares(sources: &[String]) -> io::Result<Vec<u8>> {
+    let mut shares = Vec::with_capacity(sources.len());
+    for source in sources {
+        let text = if source == "-" {
+            prompt_password("Share", false)?
+        } else {
+            fs::read_to_string(source)
+                .map_err(|e| io_error(&format!("read share '{}', {}", source, e)))?
+        };
+        let share =
+            Share::parse(&text).map_err(|e| io_error(&format!("share '{}', {}", source, e)))?;
+        shares.push(share);
+    }
+
+    shamir::combine(&shares)
+}
+
+#[cfg(unix)]
+fn cached_secret() -> Option<Vec<u8>> {
+    super::agent::get_secret()
+}
+
+#[cfg(not(unix))]
+fn cached_secret() -> Option<Vec<u8>> {
+    None
+}
+
+// only call it after the password opened a key slot, so a typo never
+// becomes the key of new files
+#[cfg(unix)]
+fn cache_secret(secret: &[u8]) {
+    super::agent::put_secret(secret)
+}
+
+#[cfg(not(unix))]
+fn cache_secret(_secret: &[u8]) {}
+
+// edit encrypts again by password only, refuse files it can not keep
+// as they are
+fn check_reencrypt(file_crypt: &mut FileCrypt, path: &Path) -> io::Result<()> {
+    let loss = file_crypt.password_only_loss(path)?;
+    if loss.is_empty() {
+        return Ok(());
+    }
+
+    Err(io_error(&format!(
+        "{:?} has {}, will be lost if encrypted again, refused",
+        path,
+        loss.join(", ")
+    )))
+}
+
+// prompt in stderr, stdout may be piped (eg. cat)
+fn prompt_password(name: &str, confirm: bool) -> io::Result<String> {
+    let pass = rpassword::prompt_password_stderr(&format!("{:>16}: ", name))?;
+    if !confirm {
+        return Ok(pass);
+    }
+
+    let pass2 = rpassword::prompt_password_stderr("Confirm Password: ")?;
+    if pass != pass2 {
+        Err(io_error("passwords you provided do not match"))
+    } else {
+        Ok(pass)
+    }
+}
@@ -0,0 +1,84 @@
+use std::io;
+
+use super::{cache_secret, input_password, opens_file, parse_args, prompt_password, run_tasks};
+use super::super::config::Profile;
+use super::super::scan::{list_in_place_tasks, prescan_encrypted, Prescan};
+use super::super::task::{Mode, Options, TaskRuner};
+use super::super::util::io_error;
+
+pub const USAGE: &str = "
+Re-encrypt files with a new password, in place. Files in the latest
+version only get their data key rewrapped in header, older ones are
+fully re-encrypted to the latest version.
+
+Usage:
+    eakio rekey <src>... [-n] [--hidden] [--exclude=<pattern>...] [--include=<pattern>...] [--parallel=<N>]
+    eakio rekey (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+    -n --dryrun          Only show what should be do.
+    --hidden             Include hidden files.
+    --exclude=<pattern>  Exclude files match pattern.
+    --include=<pattern>  Only include files match pattern.
+    --parallel=<N>       Parallel run, -1 use cpu count.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_src: Vec<String>,
+    flag_dryrun: bool,
+    flag_hidden: bool,
+    flag_exclude: Vec<String>,
+    flag_include: Vec<String>,
+    flag_parallel: Option<i32>,
+}
+
+pub fn execute(argv: Vec<String>, profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+
+    // dir and link records are encrypted files too
+    let mut filter = profile.filter(&args.flag_exclude, &args.flag_include)?;
+    let tasks = list_in_place_tasks(&args.arg_src, profile.hidden(args.flag_hidden), &mut filter, true)?;
+    let tasks = prescan_encrypted(tasks, Mode::Rekey, Prescan::default());
+
+    info!("Found {} files to rekey", tasks.len());
+    if tasks.is_empty() {
+        return Ok(());
+    }
+
+    // only password is changed, files wrapped by kms have none
+    if profile.kms(&None).is_some() {
+        return Err(io_error("rekey changes password, not work with kms in profile"));
+    }
+    // bad scheme fails before password asked
+    let padding = profile.padding(&None)?;
+
+    let secret = input_password(profile, "Old Password", false, &|s| opens_file(s, &tasks[0].src))?;
+    let new_secret = prompt_password("New Password", true)?.into_bytes();
+    let opts = Options {
+        dry_run: args.flag_dryrun,
+        ..Default::default()
+    };
+    let mut runer = TaskRuner::rekey(&secret, &new_secret, opts);
+    // full re-encrypted old ones are padded, envelope files kept as they are
+    runer.set_padding(padding);
+    let done = run_tasks(&mut runer, &tasks, profile.parallel(args.flag_parallel));
+
+    // dry run does nothing, not a failure
+    if args.flag_dryrun {
+        return Ok(());
+    }
+    let failed = done.iter().filter(|&&ok| !ok).count();
+    if failed > 0 {
+        return Err(io_error(&format!(
+            "{} files not rekeyed, they keep the old password",
+            failed
+        )));
+    }
+
+    // old one in agent is useless now
+    cache_secret(&new_secret);
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use std::io;
+
+use super::parse_args;
+use super::super::config::Profile;
+use super::super::crypto::random_key;
+use super::super::shamir;
+
+pub const USAGE: &str = "
+Generate a random key and split it into printable shares, one per line.
+Any <K> of them unlock files encrypted with '--share', fewer ones tell
+nothing about the key. The key itself is never shown or stored, give
+each share to a different person.
+
+Usage:
+    eakio split-key --shares=<N> --threshold=<K>
+    eakio split-key (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+    --shares=<N>         Number of shares, at most 255.
+    --threshold=<K>      Shares needed to unlock, at least 2.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_shares: usize,
+    flag_threshold: usize,
+}
+
+pub fn execute(argv: Vec<String>, _profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+
+    let mut key = random_key()?;
+    let shares = shamir::split(&key, args.flag_threshold, args.flag_shares);
+    key.fill(0);
+
+    for share in shares? {
+        println!("{}", share);
+    }
+    info!(
+        "Split key into {} shares, {} of them needed",
+        args.flag_shares, args.flag_threshold
+    );
+
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::slice;
+
+use super::{check_dest_dir, input_secret, load_kms, parse_args, run_tasks};
+use super::super::config::Profile;
+use super::super::file::FileCrypt;
+use super::super::scan::{apply_extension, build_tasks, list_src_files, src_dirs, ListOptions};
+use super::super::sync::SyncState;
+use super::super::task::{validate_tasks, Mode, Options, Task, TaskRuner};
+
+pub const USAGE: &str = "
+Encrypt new or changed files in src to dest dir.
+
+Usage:
+    eakio sync <src> <dest> [-n] [--delete] [--hidden] [--no-ext] [--exclude=<pattern>...] [--include=<pattern>...] [--parallel=<N>]
+    eakio sync (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+    -n --dryrun          Only show what should be do.
+    --delete             Delete dest file which src deleted.
+    --hidden             Include hidden files.
+    --no-ext             Not append '.eak' extension to dest.
+    --exclude=<pattern>  Exclude files match pattern.
+    --include=<pattern>  Only include files match pattern.
+    --parallel=<N>       Parallel run, -1 use cpu count.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_src: String,
+    arg_dest: String,
+    flag_dryrun: bool,
+    flag_delete: bool,
+    flag_hidden: bool,
+    flag_no_ext: bool,
+    flag_exclude: Vec<String>,
+    flag_include: Vec<String>,
+    flag_parallel: Option<i32>,
+}
+
+pub fn execute(argv: Vec<String>, profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+    check_dest_dir("sync", &args.arg_dest)?;
+    let dest = PathBuf::from(&args.arg_dest);
+
+    let mut filter = profile.filter(&args.flag_exclude, &args.flag_include)?;
+    let list_opts = ListOptions {
+        hidden: profile.hidden(args.flag_hidden),
+        ..Default::default()
+    };
+    let files = list_src_files(slice::from_ref(&args.arg_src), &list_opts, &mut filter)?;
+    let mut tasks = build_tasks(&files, &dest, true);
+    if !profile.no_ext(args.flag_no_ext) {
+        apply_extension(&mut tasks, Mode::Encrypt);
+    }
+    let tasks = validate_tasks(&src_dirs(&files), &dest, tasks)?;
+
+    // bad scheme fails before password asked
+    let padding = profile.padding(&None)?;
+    let kms = load_kms(profile, &None)?;
+    let secret = input_secret(profile, &kms, &[], true, &|_| true)?;
+    let mut file_crypt = FileCrypt::new(&secret);
+    if let Some(ref kms) = kms {
+        file_crypt.set_kms(&**kms);
+    }
+    // state keys relative to roots, same state whatever the current dir
+    let src = Path::new(&args.arg_src);
+    let src_root = if src.is_dir() {
+        src
+    } else {
+        src.parent().unwrap_or(src)
+    };
+    let mut state = SyncState::load(src_root, &dest, &mut file_crypt)?;
+
+    // only new or changed files need encrypt
+    let mut changed = Vec::<Task>::new();
+    let mut entries = Vec::new();
+    for task in &tasks {
+        if let Some(entry) = state.check(task)? {
+            changed.push(task.clone());
+            entries.push(entry);
+        }
+    }
+    info!(
+        "Found {} files, {} new or changed to sync",
+        tasks.len(),
+        changed.len()
+    );
+
+    let opts = Options {
+        overwrite: true,
+        dry_run: args.flag_dryrun,
+        ..Default::default()
+    };
+    let mut runer = TaskRuner::new(&secret, Mode::Encrypt, opts);
+    if let Some(ref kms) = kms {
+        runer.set_kms(&**kms);
+    }
+    runer.set_padding(padding);
+    let done = run_tasks(&mut runer, &changed, profile.parallel(args.flag_parallel));
+
+    for (entry, ok) in entries.into_iter().zip(done) {
+        if ok {
+            state.update(entry);
+        }
+    }
+
+    if args.flag_delete {
+        let alive: HashSet<PathBuf> = tasks.iter().map(|t| t.src.clone()).collect();
+        for entry in state.prune(&alive) {
+            // never delete outside dest, even the state is corrupted
+            let path = match state.dest_path(&entry) {
+                Ok(path) => path,
+                Err(e) => {
+                    error!("delete: {:?} ({})", entry.dest, e);
+                    continue;
+                }
+            };
+            if args.flag_dryrun {
+                info!("delete: {:?} (dry run)", path);
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => info!("delete: {:?} (success)", path),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    error!("delete: {:?} ({})", path, e);
+                    state.update(entry);
+                }
+            }
+        }
+    }
+
+    if !args.flag_dryrun {
+        state.save(&mut file_crypt)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use std::io;
+
+use super::{cache_secret, input_password, opens_file, parse_args, run_tasks};
+use super::super::config::Profile;
+use super::super::scan::{list_in_place_tasks, prescan_encrypted, Prescan};
+use super::super::task::{Mode, Options, TaskRuner};
+
+pub const USAGE: &str = "
+Rewrite files in old version to the latest version, in place.
+
+Usage:
+    eakio upgrade <src>... [-n] [--hidden] [--exclude=<pattern>...] [--include=<pattern>...] [--parallel=<N>]
+    eakio upgrade (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+    -n --dryrun          Only show what should be do.
+    --hidden             Include hidden files.
+    --exclude=<pattern>  Exclude files match pattern.
+    --include=<pattern>  Only include files match pattern.
+    --parallel=<N>       Parallel run, -1 use cpu count.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_src: Vec<String>,
+    flag_dryrun: bool,
+    flag_hidden: bool,
+    flag_exclude: Vec<String>,
+    flag_include: Vec<String>,
+    flag_parallel: Option<i32>,
+}
+
+pub fn execute(argv: Vec<String>, profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+
+    let mut filter = profile.filter(&args.flag_exclude, &args.flag_include)?;
+    let tasks = list_in_place_tasks(&args.arg_src, profile.hidden(args.flag_hidden), &mut filter, true)?;
+    let scan = Prescan {
+        only_old: true,
+        ..Default::default()
+    };
+    let tasks = prescan_encrypted(tasks, Mode::Upgrade, scan);
+
+    info!("Found {} files in old version to upgrade", tasks.len());
+    if tasks.is_empty() {
+        return Ok(());
+    }
+
+    let secret = input_password(profile, "Password", false, &|s| opens_file(s, &tasks[0].src))?;
+    let opts = Options {
+        dry_run: args.flag_dryrun,
+        ..Default::default()
+    };
+    let mut runer = TaskRuner::upgrade(&secret, opts);
+    let done = run_tasks(&mut runer, &tasks, profile.parallel(args.flag_parallel));
+    if done.iter().any(|&ok| ok) {
+        cache_secret(&secret);
+    }
+
+    Ok(())
+}
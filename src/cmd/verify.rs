@@ -0,0 +1,127 @@
+use std::io;
+use std::path::Path;
+
+use base64;
+
+use super::{cache_secret, input_secret, parse_args};
+use super::super::config::Profile;
+use super::super::file::verify_signature;
+use super::super::manifest::Manifest;
+use super::super::sign::read_public_key;
+use super::super::util::io_error;
+
+pub const USAGE: &str = "
+Check signatures of encrypted files, no password needed. With trusted
+signers, files signed by others are failed too.
+
+With manifest from 'encrypt --manifest', check files listed in it are
+the same, missing or replaced ones are failed, so are encrypted files
+in its dir not listed. Password
+needed if manifest not signed, signed one needs --trusted-signer.
+
+Usage:
+    eakio verify <file>... [--trusted-signer=<pubkey>...]
+    eakio verify --manifest=<file> [--share=<file>...] [--trusted-signer=<pubkey>...]
+    eakio verify (-h | --help)
+
+Options:
+    -h --help                 Show this screen.
+    --trusted-signer=<pubkey> Public key from keygen, or file of it.
+    --manifest=<file>         Manifest to check.
+    --share=<file>            Share of key from split-key, if manifest
+                              authenticated by shared key.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_file: Vec<String>,
+    flag_manifest: Option<String>,
+    flag_share: Vec<String>,
+    flag_trusted_signer: Vec<String>,
+}
+
+pub fn execute(argv: Vec<String>, profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+    let trusted = args
+        .flag_trusted_signer
+        .iter()
+        .map(|key| read_public_key(key))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    if let Some(ref path) = args.flag_manifest {
+        return verify_manifest(Path::new(path), &args.flag_share, &trusted, profile);
+    }
+
+    let mut failed = 0;
+    for path in &args.arg_file {
+        match verify_file(Path::new(path), &trusted) {
+            Ok(signer) => info!("{}: signed by '{}'", path, signer),
+            Err(e) => {
+                error!("{}: {}", path, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(io_error(&format!("{} files not verified", failed)));
+    }
+    Ok(())
+}
+
+fn verify_manifest(
+    path: &Path,
+    shares: &[String],
+    trusted: &[Vec<u8>],
+    profile: &Profile,
+) -> io::Result<()> {
+    let manifest = Manifest::load(path)?;
+    let secret = if manifest.need_secret() && trusted.is_empty() {
+        input_secret(profile, &None, shares, false, &|s| {
+            manifest.authenticate(s, trusted).is_ok()
+        })?
+    } else {
+        Vec::new()
+    };
+
+    let signer = manifest.authenticate(&secret, trusted)?;
+    if manifest.need_secret() && shares.is_empty() {
+        cache_secret(&secret);
+    }
+    match signer {
+        Some(signer) => info!("{:?}: signed by '{}'", path, base64::encode(&signer)),
+        None => info!("{:?}: authenticated by password", path),
+    }
+
+    let report = manifest.check(path)?;
+    for name in &report.missing {
+        error!("{}: missing", name);
+    }
+    for name in &report.changed {
+        error!("{}: changed", name);
+    }
+    for name in &report.extra {
+        error!("{}: not in manifest", name);
+    }
+
+    if !report.is_ok() {
+        return Err(io_error(&format!(
+            "{} missing, {} changed, {} extra files",
+            report.missing.len(),
+            report.changed.len(),
+            report.extra.len()
+        )));
+    }
+    info!("All {} files in manifest verified", manifest.len());
+    Ok(())
+}
+
+fn verify_file(path: &Path, trusted: &[Vec<u8>]) -> io::Result<String> {
+    let signer = verify_signature(path)?;
+    let signer_text = base64::encode(&signer);
+
+    if !trusted.is_empty() && !trusted.contains(&signer) {
+        return Err(io_error(&format!("signer '{}' not trusted", signer_text)));
+    }
+    Ok(signer_text)
+}
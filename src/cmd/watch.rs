@@ -0,0 +1,137 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::{check_dest_dir, input_secret, load_kms, parse_args, run_tasks};
+use super::super::config::Profile;
+use super::super::scan::{apply_extension, build_tasks, PathGroup};
+use super::super::task::{Kind, Mode, Options, TaskRuner};
+use super::super::util::{expand_tilde_path, io_error, move_file};
+use super::super::watch::DirWatcher;
+
+pub const USAGE: &str = "
+Watch src dir, encrypt files to dest dir when they are written.
+
+Usage:
+    eakio watch <src> <dest> [--delay=<secs>] [--remove-source [--shred] | --quarantine=<dir>] [--hidden] [--no-ext] [--exclude=<pattern>...] [--include=<pattern>...] [--parallel=<N>]
+    eakio watch (-h | --help)
+
+Options:
+    -h --help            Show this screen.
+    --delay=<secs>       Wait seconds after last write [default: 2].
+    --remove-source      Remove src file after encrypted file verified.
+    --shred              Overwrite src file before remove.
+    --quarantine=<dir>   Move src file to dir after encrypted.
+    --hidden             Include hidden files.
+    --no-ext             Not append '.eak' extension to dest.
+    --exclude=<pattern>  Exclude files match pattern.
+    --include=<pattern>  Only include files match pattern.
+    --parallel=<N>       Parallel run, -1 use cpu count.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_src: String,
+    arg_dest: String,
+    flag_delay: u64,
+    flag_remove_source: bool,
+    flag_shred: bool,
+    flag_quarantine: Option<String>,
+    flag_hidden: bool,
+    flag_no_ext: bool,
+    flag_exclude: Vec<String>,
+    flag_include: Vec<String>,
+    flag_parallel: Option<i32>,
+}
+
+pub fn execute(argv: Vec<String>, profile: &Profile) -> io::Result<()> {
+    let args: Args = parse_args(USAGE, argv);
+    check_dest_dir("watch", &args.arg_dest)?;
+
+    let root = fs::canonicalize(&*expand_tilde_path(&args.arg_src))?;
+    if !root.is_dir() {
+        return Err(io_error(&format!("watch src {:?} must a dir", root)));
+    }
+
+    // dest or quarantine in src will be watched again, never stop
+    let dest = PathBuf::from(&args.arg_dest);
+    fs::create_dir_all(&dest)?;
+    if fs::canonicalize(&dest)?.starts_with(&root) {
+        return Err(io_error("watch dest can not inside src"));
+    }
+
+    let quarantine = match args.flag_quarantine {
+        Some(ref dir) => {
+            let dir = PathBuf::from(&*expand_tilde_path(dir));
+            fs::create_dir_all(&dir)?;
+            if fs::canonicalize(&dir)?.starts_with(&root) {
+                return Err(io_error("watch quarantine can not inside src"));
+            }
+            Some(dir)
+        }
+        None => None,
+    };
+
+    // bad scheme fails before password asked
+    let padding = profile.padding(&None)?;
+    let kms = load_kms(profile, &None)?;
+    let secret = input_secret(profile, &kms, &[], true, &|_| true)?;
+    let opts = Options {
+        overwrite: true,
+        remove_source: args.flag_remove_source,
+        shred: args.flag_shred,
+        ..Default::default()
+    };
+    let mut runer = TaskRuner::new(&secret, Mode::Encrypt, opts);
+    if let Some(ref kms) = kms {
+        runer.set_kms(&**kms);
+    }
+    runer.set_padding(padding);
+    let parallel = profile.parallel(args.flag_parallel);
+    let no_ext = profile.no_ext(args.flag_no_ext);
+
+    let mut filter = profile.filter(&args.flag_exclude, &args.flag_include)?;
+    let watcher = DirWatcher::new(
+        &root,
+        Duration::from_secs(args.flag_delay),
+        profile.hidden(args.flag_hidden),
+    )?;
+    info!("Watching {:?} to encrypt", root);
+
+    loop {
+        let mut paths = watcher.next_batch()?;
+        paths.retain(|p| !filter.is_path_excluded(&root, p));
+        if paths.is_empty() {
+            continue;
+        }
+
+        let group = PathGroup {
+            path: root.clone(),
+            is_file: false,
+            subs: paths.into_iter().map(|p| (p, Kind::File)).collect(),
+        };
+        let mut tasks = build_tasks(&[group], &dest, true);
+        if !no_ext {
+            apply_extension(&mut tasks, Mode::Encrypt);
+        }
+        let done = run_tasks(&mut runer, &tasks, parallel);
+
+        if let Some(ref dir) = quarantine {
+            for (task, ok) in tasks.iter().zip(done) {
+                if ok {
+                    quarantine_source(&root, &task.src, dir);
+                }
+            }
+        }
+    }
+}
+
+// move src to quarantine dir after encrypted
+fn quarantine_source(root: &Path, src: &Path, dir: &Path) {
+    let to = dir.join(src.strip_prefix(root).unwrap());
+    match move_file(src, &to) {
+        Ok(()) => info!("quarantine: {:?} -> {:?}", src, to),
+        Err(e) => error!("quarantine: {:?} ({})", src, e),
+    }
+}
@@ -0,0 +1,357 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use toml;
+
+use super::ignore::Filter;
+use super::padding::Padding;
+use super::util::{expand_tilde_path, io_error};
+
+pub const DEFAULT_CONFIG: &str = "~/.config/eakio/config.toml";
+
+// the only cipher eakio use now, config can name it, but not change it
+const CIPHER: &str = "aes-256-gcm";
+
+/// options in config file, all optional, command line flags override them
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub parallel: Option<i32>,
+    pub hidden: Option<bool>,
+    pub skip: Option<bool>,
+    pub overwrite: Option<bool>,
+    pub no_ext: Option<bool>,
+    pub keep_dirs: Option<bool>,
+    pub exclude: Option<Vec<String>>,
+    pub include: Option<Vec<String>>,
+    pub cipher: Option<String>,
+    pub password_file: Option<String>,
+    pub password_command: Option<String>,
+    pub password_env: Option<String>,
+    pub kms: Option<String>,
+    pub pad: Option<String>,
+}
+
+// config file like
+//
+// [default]
+// parallel = -1
+//
+// [profile.backup]
+// exclude = ["*.tmp"]
+// password_file = "~/.eakio-pass"
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    default: Profile,
+    #[serde(default)]
+    profile: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// load config from path, or the default config if it exists
+    pub fn load(path: Option<&str>) -> io::Result<Config> {
+        let (path, required) = match path {
+            Some(path) => (PathBuf::from(&*expand_tilde_path(path)), true),
+            None => (default_config_path(), false),
+        };
+
+        if !required && !path.is_file() {
+            return Ok(Config::default());
+        }
+
+        let mut content = String::new();
+        File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .map_err(|e| io_error(&format!("load config {:?}, {}", path, e)))?;
+
+        Config::parse(&content).map_err(|e| io_error(&format!("config {:?}, {}", path, e)))
+    }
+
+    fn parse(content: &str) -> io::Result<Config> {
+        let config: Config = toml::from_str(content).map_err(|e| io_error(&format!("{}", e)))?;
+
+        config.default.validate("default")?;
+        for (name, profile) in &config.profile {
+            profile.validate(name)?;
+        }
+
+        Ok(config)
+    }
+
+    /// default options, merged with named profile if given
+    pub fn profile(&self, name: Option<&str>) -> io::Result<Profile> {
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(self.default.clone()),
+        };
+
+        let profile = match self.profile.get(name) {
+            Some(profile) => self.default.merge(profile),
+            None => return Err(io_error(&format!("profile '{}' not found in config", name))),
+        };
+        // each is fine, merged may not
+        profile.validate(name)?;
+        Ok(profile)
+    }
+}
+
+impl Profile {
+    // values in other take precedence, password source as a whole
+    fn merge(&self, other: &Profile) -> Profile {
+        let source = if other.password_file.is_some() || other.password_command.is_some()
+            || other.password_env.is_some()
+        {
+            other
+        } else {
+            self
+        };
+
+        Profile {
+            parallel: other.parallel.or(self.parallel),
+            hidden: other.hidden.or(self.hidden),
+            skip: other.skip.or(self.skip),
+            overwrite: other.overwrite.or(self.overwrite),
+            no_ext: other.no_ext.or(self.no_ext),
+            keep_dirs: other.keep_dirs.or(self.keep_dirs),
+            exclude: other.exclude.clone().or_else(|| self.exclude.clone()),
+            include: other.include.clone().or_else(|| self.include.clone()),
+            cipher: other.cipher.clone().or_else(|| self.cipher.clone()),
+            password_file: source.password_file.clone(),
+            password_command: source.password_command.clone(),
+            password_env: source.password_env.clone(),
+            kms: other.kms.clone().or_else(|| self.kms.clone()),
+            pad: other.pad.clone().or_else(|| self.pad.clone()),
+        }
+    }
+
+    /// command line flag first, or the config value
+    pub fn parallel(&self, flag: Option<i32>) -> Option<i32> {
+        flag.or(self.parallel)
+    }
+
+    pub fn hidden(&self, flag: bool) -> bool {
+        switch(flag, self.hidden)
+    }
+
+    pub fn no_ext(&self, flag: bool) -> bool {
+        switch(flag, self.no_ext)
+    }
+
+    pub fn keep_dirs(&self, flag: bool) -> bool {
+        switch(flag, self.keep_dirs)
+    }
+
+    pub fn kms(&self, flag: &Option<String>) -> Option<String> {
+        flag.clone().or_else(|| self.kms.clone())
+    }
+
+    /// padding of encrypted files, none if not set
+    pub fn padding(&self, flag: &Option<String>) -> io::Result<Padding> {
+        match flag.as_deref().or(self.pad.as_deref()) {
+            Some(pad) => Padding::parse(pad),
+            None => Ok(Padding::None),
+        }
+    }
+
+    /// (skip, overwrite), config not used if any of them given in flags
+    pub fn exists(&self, skip: bool, overwrite: bool) -> (bool, bool) {
+        if skip || overwrite {
+            (skip, overwrite)
+        } else {
+            (self.skip.unwrap_or(false), self.overwrite.unwrap_or(false))
+        }
+    }
+
+    /// filter by (excludes, includes) flags or config patterns
+    pub fn filter(&self, excludes: &[String], includes: &[String]) -> io::Result<Filter> {
+        let excludes = match self.exclude {
+            Some(ref patterns) if excludes.is_empty() => patterns,
+            _ => excludes,
+        };
+        let includes = match self.include {
+            Some(ref patterns) if includes.is_empty() => patterns,
+            _ => includes,
+        };
+
+        Filter::new(excludes, includes)
+    }
+
+    fn validate(&self, name: &str) -> io::Result<()> {
+        if self.skip == Some(true) && self.overwrite == Some(true) {
+            return Err(io_error(&format!(
+                "profile '{}' skip and overwrite can not both be true",
+                name
+            )));
+        }
+
+        if let Some(ref cipher) = self.cipher {
+            if cipher.to_lowercase() != CIPHER {
+                return Err(io_error(&format!(
+                    "profile '{}' cipher '{}' not support, only '{}'",
+                    name, cipher, CIPHER
+                )));
+            }
+        }
+
+        let sources = [
+            &self.password_file,
+            &self.password_command,
+            &self.password_env,
+        ];
+        if sources.iter().filter(|s| s.is_some()).count() > 1 {
+            return Err(io_error(&format!(
+                "profile '{}' only one password source can be set",
+                name
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// read password from the source in profile, None if no source
+    pub fn read_password(&self) -> io::Result<Option<String>> {
+        let pass = if let Some(ref path) = self.password_file {
+            let path = expand_tilde_path(path);
+            let mut content = String::new();
+            File::open(&*path)
+                .and_then(|mut f| f.read_to_string(&mut content))
+                .map_err(|e| io_error(&format!("read password file '{}', {}", path, e)))?;
+            content
+        } else if let Some(ref cmd) = self.password_command {
+            let output = shell_command(cmd).output()?;
+            if !output.status.success() {
+                return Err(io_error(&format!(
+                    "password command '{}' failed, {}",
+                    cmd, output.status
+                )));
+            }
+            String::from_utf8(output.stdout)
+                .map_err(|_| io_error("password command output not utf-8"))?
+        } else if let Some(ref name) = self.password_env {
+            env::var(name).map_err(|e| io_error(&format!("password env '{}', {}", name, e)))?
+        } else {
+            return Ok(None);
+        };
+
+        // only the first line, so trailing newline not in password
+        let pass = pass.lines().next().unwrap_or("").to_owned();
+        if pass.is_empty() {
+            return Err(io_error("password from config source is empty"));
+        }
+
+        Ok(Some(pass))
+    }
+}
+
+// flags can only be given, not unset. given flag wins, or the config
+// value, off if neither
+fn switch(flag: bool, config: Option<bool>) -> bool {
+    match (flag, config) {
+        (true, _) => true,
+        (false, Some(value)) => value,
+        (false, None) => false,
+    }
+}
+
+fn default_config_path() -> PathBuf {
+    match env::var("XDG_CONFIG_HOME") {
+        Ok(ref dir) if !dir.is_empty() => Path::new(dir).join("eakio").join("config.toml"),
+        _ => PathBuf::from(&*expand_tilde_path(DEFAULT_CONFIG)),
+    }
+}
+
+#[cfg(unix)]
+fn shell_command(cmd: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(cmd);
+    command
+}
+
+#[cfg(not(unix))]
+fn shell_command(cmd: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(cmd);
+    command
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[default]
+parallel = -1
+hidden = true
+exclude = ["*.swp"]
+
+[profile.backup]
+exclude = ["*.tmp", "cache/"]
+skip = true
+cipher = "AES-256-GCM"
+password_env = "EAKIO_TEST_PASS"
+"#;
+
+    #[test]
+    fn test_profile() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        let default = config.profile(None).unwrap();
+        assert_eq!(default.parallel, Some(-1));
+        assert_eq!(default.exclude, Some(vec!["*.swp".to_owned()]));
+        assert_eq!(default.skip, None);
+
+        let backup = config.profile(Some("backup")).unwrap();
+        assert_eq!(backup.parallel, Some(-1));
+        assert_eq!(backup.hidden, Some(true));
+        assert_eq!(backup.skip, Some(true));
+        assert_eq!(
+            backup.exclude,
+            Some(vec!["*.tmp".to_owned(), "cache/".to_owned()])
+        );
+
+        assert!(config.profile(Some("nothing")).is_err());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(Config::parse("[default]\nparalel = 1\n").is_err());
+        assert!(Config::parse("[default]\ncipher = \"chacha20\"\n").is_err());
+        assert!(Config::parse("[default]\nkdf_cost = 10\n").is_err());
+        assert!(Config::parse("[profile.x]\nskip = true\noverwrite = true\n").is_err());
+        assert!(
+            Config::parse("[profile.x]\npassword_env = \"A\"\npassword_file = \"b\"\n").is_err()
+        );
+        assert!(Config::parse("").is_ok());
+
+        // invalid only after merged
+        let config =
+            Config::parse("[default]\nskip = true\n[profile.x]\noverwrite = true\n").unwrap();
+        assert!(config.profile(Some("x")).is_err());
+    }
+
+    #[test]
+    fn test_merge_password_source() {
+        let config = Config::parse(
+            "[default]\npassword_file = \"a\"\n[profile.x]\npassword_env = \"B\"\n",
+        ).unwrap();
+        let x = config.profile(Some("x")).unwrap();
+        assert_eq!(x.password_file, None);
+        assert_eq!(x.password_env, Some("B".to_owned()));
+    }
+
+    #[test]
+    fn test_read_password() {
+        env::set_var("EAKIO_TEST_PASS", "secret\n");
+        let config = Config::parse(CONFIG).unwrap();
+        let backup = config.profile(Some("backup")).unwrap();
+        assert_eq!(backup.read_password().unwrap(), Some("secret".to_owned()));
+        assert_eq!(config.profile(None).unwrap().read_password().unwrap(), None);
+    }
+}
@@ -5,14 +5,15 @@ use std::result;
 use ring::{aead, digest, hkdf, hmac};
 use ring::rand::{SecureRandom, SystemRandom};
 
-static CIPHER: &'static aead::Algorithm = &aead::AES_256_GCM;
-static DIGEST: &'static digest::Algorithm = &digest::SHA256;
+static CIPHER: &aead::Algorithm = &aead::AES_256_GCM;
+static DIGEST: &digest::Algorithm = &digest::SHA256;
 
 pub type Result<T> = result::Result<T, Error>;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Error {
     GenSalt,
+    GenKey,
     SaltLenNotMatch(usize),
     OpenKey,
     SealKey,
@@ -49,6 +50,23 @@ impl Salt {
         Ok(Salt { len, bytes: buf })
     }
 
+    /// salt keyed by secret from data, the same data always get the same
+    /// salt, so the same key and nonces, only used when data must be
+    /// encrypted to the same output (eg. git filter)
+    pub fn derive(secret: &[u8], data: &[u8]) -> Salt {
+        let len = Salt::len();
+
+        // not use secret directly, keep it away from the encrypt key
+        let master = hmac::SigningKey::new(DIGEST, secret);
+        let sub = hmac::sign(&master, INFO_SALT.as_bytes());
+        let key = hmac::SigningKey::new(DIGEST, sub.as_ref());
+
+        let mut bytes = [0u8; digest::MAX_OUTPUT_LEN];
+        bytes[..len].copy_from_slice(&hmac::sign(&key, data).as_ref()[..len]);
+
+        Salt { len, bytes }
+    }
+
     #[inline]
     pub fn len() -> usize {
         hmac::recommended_key_len(DIGEST)
@@ -65,7 +83,17 @@ impl Salt {
     }
 }
 
+/// random key, used as secret of Crypto
+pub fn random_key() -> Result<Vec<u8>> {
+    let mut key = vec![0u8; CIPHER.key_len()];
+    SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| Error::GenKey)?;
+    Ok(key)
+}
+
 const INFO_KEY: &str = "hello kelsi";
+const INFO_SALT: &str = "kelsi deterministic salt";
 
 #[allow(dead_code)]
 pub struct Crypto {
@@ -83,12 +111,7 @@ pub struct Crypto {
 impl Crypto {
     pub fn new(secret: &[u8], salt: &Salt) -> Result<Crypto> {
         let key_len = CIPHER.key_len();
-        let mut key = Vec::with_capacity(key_len);
-
-        // not need init it
-        unsafe {
-            key.set_len(key_len);
-        }
+        let mut key = vec![0u8; key_len];
         hkdf::extract_and_expand(
             &salt.get_signing_key(),
             secret,
@@ -166,6 +189,7 @@ impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::GenSalt => write!(fmt, "generate salt error"),
+            Error::GenKey => write!(fmt, "generate key error"),
             Error::SaltLenNotMatch(need) => write!(fmt, "salt length not match, need {}", need),
             Error::OpenKey => write!(fmt, "crypto ring open key error"),
             Error::SealKey => write!(fmt, "crypto ring seal key error"),
@@ -180,7 +204,7 @@ impl fmt::Display for Error {
 
 impl From<Error> for io::Error {
     fn from(err: Error) -> io::Error {
-        io::Error::new(io::ErrorKind::Other, format!("{}", err))
+        io::Error::other(format!("{}", err))
     }
 }
 
@@ -225,12 +249,23 @@ mod test {
 
         // test 0 size buf
         let out_len = crypto.encrypt(&mut buf[..], 0).unwrap();
-        assert_eq!(out_len, 0 + Crypto::tag_len());
+        assert_eq!(out_len, Crypto::tag_len());
 
         let len = crypto.decrypt(&mut buf[..out_len]).unwrap();
         assert_eq!(0, len);
     }
 
+    #[test]
+    fn test_salt_derive() {
+        let salt1 = Salt::derive(b"secret", b"data");
+        let salt2 = Salt::derive(b"secret", b"data");
+        assert_eq!(salt1.get_bytes(), salt2.get_bytes());
+        assert_eq!(salt1.get_bytes().len(), Salt::len());
+
+        assert_ne!(salt1.get_bytes(), Salt::derive(b"secret", b"data2").get_bytes());
+        assert_ne!(salt1.get_bytes(), Salt::derive(b"secret2", b"data").get_bytes());
+    }
+
     #[test]
     fn test_crypto_multi_buf() {
         let salt = Salt::new().unwrap();
@@ -0,0 +1,220 @@
+use std::fmt;
+use std::io;
+
+use super::util::io_error;
+
+// one line in env file, comments and blank lines kept when write back
+#[derive(Clone, Debug, PartialEq)]
+enum Line {
+    Pair(String, String),
+    Other(String),
+}
+
+/// dotenv format file, KEY=VALUE per line, value can be quoted
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EnvFile {
+    lines: Vec<Line>,
+}
+
+impl EnvFile {
+    pub fn parse(content: &str) -> io::Result<EnvFile> {
+        let mut lines = Vec::new();
+
+        for (i, raw) in content.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                lines.push(Line::Other(raw.to_owned()));
+                continue;
+            }
+
+            let line = match line.strip_prefix("export ") {
+                Some(rest) => rest.trim_start(),
+                None => line,
+            };
+
+            let pos = match line.find('=') {
+                Some(pos) => pos,
+                None => return Err(io_error(&format!("env line {} has no '='", i + 1))),
+            };
+            let key = line[..pos].trim();
+            if !is_valid_key(key) {
+                return Err(io_error(&format!("env line {} invalid key '{}'", i + 1, key)));
+            }
+
+            let value = parse_value(line[pos + 1..].trim())
+                .map_err(|e| io_error(&format!("env line {} {}", i + 1, e)))?;
+            lines.push(Line::Pair(key.to_owned(), value));
+        }
+
+        Ok(EnvFile { lines })
+    }
+
+    /// all key values, later one win if key duplicated
+    pub fn vars(&self) -> Vec<(&str, &str)> {
+        let mut vars: Vec<(&str, &str)> = Vec::new();
+        for line in &self.lines {
+            if let Line::Pair(ref k, ref v) = *line {
+                vars.retain(|&(key, _)| key != k.as_str());
+                vars.push((k, v));
+            }
+        }
+        vars
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars()
+            .into_iter()
+            .find(|&(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// replace value in place if key exists, or append it
+    pub fn set(&mut self, key: &str, value: &str) -> io::Result<()> {
+        if !is_valid_key(key) {
+            return Err(io_error(&format!("invalid env key '{}'", key)));
+        }
+
+        let pair = Line::Pair(key.to_owned(), value.to_owned());
+        match self.lines.iter().position(|line| line.is_key(key)) {
+            Some(pos) => {
+                // duplicated ones after it are dropped
+                let tail = self.lines.split_off(pos + 1);
+                self.lines[pos] = pair;
+                self.lines
+                    .extend(tail.into_iter().filter(|line| !line.is_key(key)));
+            }
+            None => self.lines.push(pair),
+        }
+        Ok(())
+    }
+
+    /// returns false if key not exists
+    pub fn unset(&mut self, key: &str) -> bool {
+        let len = self.lines.len();
+        self.lines.retain(|line| !line.is_key(key));
+        len != self.lines.len()
+    }
+}
+
+impl Line {
+    fn is_key(&self, key: &str) -> bool {
+        match *self {
+            Line::Pair(ref k, _) => k == key,
+            Line::Other(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for EnvFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            match *line {
+                Line::Pair(ref k, ref v) => writeln!(f, "{}={}", k, quote_value(v))?,
+                Line::Other(ref raw) => writeln!(f, "{}", raw)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// "double" support escapes, 'single' is literal, bare value end at ' #'
+fn parse_value(value: &str) -> Result<String, String> {
+    if let Some(quoted) = value.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = quoted.chars();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('n') => out.push('\n'),
+                    Some('r') => out.push('\r'),
+                    Some('t') => out.push('\t'),
+                    Some(c) => out.push(c),
+                    None => return Err("unterminated quote".to_owned()),
+                },
+                Some(c) => out.push(c),
+                None => return Err("unterminated quote".to_owned()),
+            }
+        }
+        Ok(out)
+    } else if let Some(quoted) = value.strip_prefix('\'') {
+        match quoted.find('\'') {
+            Some(end) => Ok(quoted[..end].to_owned()),
+            None => Err("unterminated quote".to_owned()),
+        }
+    } else {
+        let value = match value.find(" #") {
+            Some(pos) => &value[..pos],
+            None => value,
+        };
+        Ok(value.trim_end().to_owned())
+    }
+}
+
+fn quote_value(value: &str) -> String {
+    let bare = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_-./:@,+%".contains(c));
+    if bare {
+        return value.to_owned();
+    }
+
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_env() {
+        let env = EnvFile::parse(
+            "# db\nDB_HOST=localhost # comment\nexport DB_PASS=\"a \\\"b\\\"\\nc\"\n\nRAW='x \\n y'\nDB_HOST=db\n",
+        ).unwrap();
+
+        assert_eq!(env.get("DB_HOST"), Some("db"));
+        assert_eq!(env.get("DB_PASS"), Some("a \"b\"\nc"));
+        assert_eq!(env.get("RAW"), Some("x \\n y"));
+        assert_eq!(env.get("NOTHING"), None);
+        assert_eq!(env.vars().len(), 3);
+
+        assert!(EnvFile::parse("NOVALUE\n").is_err());
+        assert!(EnvFile::parse("1KEY=a\n").is_err());
+        assert!(EnvFile::parse("KEY=\"a\n").is_err());
+    }
+
+    #[test]
+    fn test_edit_env() {
+        let mut env = EnvFile::parse("# keep\nA=1\nB=2\n").unwrap();
+        env.set("A", "x y").unwrap();
+        env.set("C", "a\"b").unwrap();
+        assert!(env.unset("B"));
+        assert!(!env.unset("B"));
+        assert!(env.set("bad key", "1").is_err());
+
+        let content = env.to_string();
+        assert_eq!(content, "# keep\nA=\"x y\"\nC=\"a\\\"b\"\n");
+        assert_eq!(EnvFile::parse(&content).unwrap(), env);
+    }
+}
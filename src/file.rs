@@ -1,14 +1,30 @@
-use std::fs::File;
+use std::cmp;
+use std::fs::{self, File};
 use std::io;
-use std::io::{BufReader, BufWriter, Cursor};
+use std::io::{BufReader, BufWriter, Cursor, SeekFrom};
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 
-use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+use base64;
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use ring::digest;
+use ring::signature::Ed25519KeyPair;
 
-use super::crypto::{Crypto, Salt};
+use super::crypto::{random_key, Crypto, Salt};
+use super::kms::KeyProvider;
+use super::padding::Padding;
+use super::recovery;
+use super::sign::{self, SIGNATURE_LEN};
+use super::task::tmp_path;
 use super::util::io_error;
 
+/// dir record file name, a dir record keep dir entry (and its mode)
+/// in encrypted tree, so empty dir can be restored
+pub const DIR_RECORD: &str = ".eakio-dir";
+
+/// encrypted file extension
+pub const EXTENSION: &str = "eak";
+
 const MAGIC: &[u8] = b"KELSI";
 const BLOCK_SIZE: usize = 128 * 1024;
 
@@ -32,58 +48,471 @@ const VERSION_1: u8 = 0x01;
 // +----+---------+
 const VERSION_2: u8 = 0x02;
 
+// +----+---------+
+// |    |  MAGIC  |
+// |    +---------+
+// |    | VERSION |
+// | H  +---------+
+// | E  |   SALT  |
+// | A  +---------+
+// | D  |   SIZE  |
+// |    +---------+
+// |    |   KIND  |
+// +----+---------+
+//
+// SIZE and KIND are encrypted together
+const VERSION_3: u8 = 0x03;
+
+// +----+---------+
+// |    |  MAGIC  |
+// |    +---------+
+// |    | VERSION |
+// |    +---------+
+// | H  |  SLOTS  |
+// | E  +---------+
+// | A  |   SALT  |
+// | D  +---------+
+// |    |   SIZE  |
+// |    +---------+
+// |    |   KIND  |
+// +----+---------+
+//
+// data is encrypted by a random data key (DEK), SLOTS keep the DEK
+// wrapped by key encryption keys (KEK), so changing password only
+// rewrite SLOTS. SLOTS is COUNT, then each slot KIND, LEN and DATA
+//
+// with a signer slot, Ed25519 SIGNATURE is appended after data, it
+// signs SHA256 of all before it except SLOTS, so rekey keep it valid
+//
+// with KIND_PADDED set in KIND, data is followed by zero padding and
+// padding length (u64), the length is always in the final chunk
+const VERSION_4: u8 = 0x04;
+
+// slot DATA is slot SALT and DEK wrapped by key from password and it
+const SLOT_PASSWORD: u8 = 0x01;
+// slot DATA is DEK wrapped by external key service
+const SLOT_KMS: u8 = 0x02;
+// like password slot, key from normalized recovery code
+const SLOT_RECOVERY: u8 = 0x03;
+// slot DATA is public key of signer
+const SLOT_SIGNER: u8 = 0x04;
+const MAX_SLOTS: usize = 16;
+
+const KIND_PADDED: u8 = 0x80;
+const PAD_LEN_SIZE: usize = 8;
+
+/// what the encrypted data is
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EntryKind {
+    // file content
+    File,
+    // symlink target path
+    Symlink,
+    // empty or dir mode
+    Dir,
+}
+
+impl EntryKind {
+    fn to_u8(self) -> u8 {
+        match self {
+            EntryKind::File => 0x00,
+            EntryKind::Symlink => 0x01,
+            EntryKind::Dir => 0x02,
+        }
+    }
+
+    fn from_u8(kind: u8) -> io::Result<EntryKind> {
+        match kind {
+            0x00 => Ok(EntryKind::File),
+            0x01 => Ok(EntryKind::Symlink),
+            0x02 => Ok(EntryKind::Dir),
+            _ => Err(io_error(&format!("entry kind '{}' not support", kind))),
+        }
+    }
+}
+
+struct Header {
+    kind: EntryKind,
+    // encrypted data after header, signature not included
+    data_len: usize,
+    padded: bool,
+    signed: Option<Signed>,
+}
+
+// signer and hash of signed part read so far
+struct Signed {
+    public_key: Vec<u8>,
+    ctx: digest::Context,
+}
+
+impl Signed {
+    fn new(public_key: Vec<u8>) -> Signed {
+        Signed {
+            public_key,
+            ctx: digest::Context::new(&digest::SHA256),
+        }
+    }
+
+    // signature is right after the signed part
+    fn verify<R: Read>(self, reader: &mut R) -> io::Result<()> {
+        let mut sig = [0u8; SIGNATURE_LEN];
+        reader.read_exact(&mut sig)?;
+
+        if sign::verify(&self.public_key, self.ctx.finish().as_ref(), &sig) {
+            Ok(())
+        } else {
+            Err(io_error("signature not valid"))
+        }
+    }
+}
+
+// one wrapped copy of the data key
+#[derive(Clone, Debug, PartialEq)]
+struct KeySlot {
+    kind: u8,
+    data: Vec<u8>,
+}
+
+impl KeySlot {
+    // password or recovery slot
+    fn wrap(kind: u8, secret: &[u8], dek: &[u8], salt: &Salt) -> io::Result<KeySlot> {
+        let mut buf = vec![0u8; dek.len() + Crypto::tag_len()];
+        buf[..dek.len()].copy_from_slice(dek);
+        let len = Crypto::new(secret, salt)?.encrypt(&mut buf, dek.len())?;
+
+        let mut data = salt.get_bytes().to_vec();
+        data.extend_from_slice(&buf[..len]);
+        Ok(KeySlot { kind, data })
+    }
+
+    fn kms(kms: &dyn KeyProvider, dek: &[u8]) -> io::Result<KeySlot> {
+        let data = kms.wrap_key(dek)?;
+        if data.len() > u16::MAX as usize {
+            return Err(io_error("kms wrapped key too long"));
+        }
+
+        Ok(KeySlot {
+            kind: SLOT_KMS,
+            data,
+        })
+    }
+
+    // None if not a password or recovery slot, or secret is wrong
+    fn open(&self, secret: &[u8]) -> Option<Vec<u8>> {
+        let secret = match self.kind {
+            SLOT_PASSWORD => secret.to_vec(),
+            SLOT_RECOVERY => recovery::normalize(secret),
+            _ => return None,
+        };
+        if self.data.len() < Salt::len() + Crypto::tag_len() {
+            return None;
+        }
+
+        let salt = Salt::from_bytes(&self.data[..Salt::len()]).ok()?;
+        let mut buf = self.data[Salt::len()..].to_vec();
+        let len = Crypto::new(&secret, &salt).ok()?.decrypt(&mut buf).ok()?;
+        buf.truncate(len);
+        Some(buf)
+    }
+}
+
+fn read_slots<R: Read>(reader: &mut R) -> io::Result<Vec<KeySlot>> {
+    let count = reader.read_u8()? as usize;
+    if count == 0 || count > MAX_SLOTS {
+        return Err(io_error(&format!("key slot count '{}' not valid", count)));
+    }
+
+    let mut slots = Vec::with_capacity(count);
+    for _ in 0..count {
+        let kind = reader.read_u8()?;
+        let len = reader.read_u16::<BigEndian>()? as usize;
+        let mut data = vec![0u8; len];
+        reader.read_exact(&mut data)?;
+        slots.push(KeySlot { kind, data });
+    }
+
+    Ok(slots)
+}
+
+fn write_slots<W: Write>(writer: &mut W, slots: &[KeySlot]) -> io::Result<()> {
+    writer.write_u8(slots.len() as u8)?;
+    for slot in slots {
+        writer.write_u8(slot.kind)?;
+        writer.write_u16::<BigEndian>(slot.data.len() as u16)?;
+        writer.write_all(&slot.data)?;
+    }
+    Ok(())
+}
+
+fn find_signer(slots: &[KeySlot]) -> Option<Vec<u8>> {
+    slots
+        .iter()
+        .find(|slot| slot.kind == SLOT_SIGNER)
+        .map(|slot| slot.data.clone())
+}
+
+// write data, and hash it if signed
+fn write_signed<W: Write>(writer: &mut W, signed: &mut Option<Signed>, data: &[u8]) -> io::Result<()> {
+    if let Some(ref mut signed) = *signed {
+        signed.ctx.update(data);
+    }
+    writer.write_all(data)
+}
+
+fn slots_len(slots: &[KeySlot]) -> usize {
+    1 + slots.iter().map(|slot| 1 + 2 + slot.data.len()).sum::<usize>()
+}
+
+// data key and salts of a new encrypted entry
+struct FileKeys {
+    dek: Vec<u8>,
+    salt: Salt,
+    slot_salt: Salt,
+}
+
+impl FileKeys {
+    fn random() -> io::Result<FileKeys> {
+        Ok(FileKeys {
+            dek: random_key()?,
+            salt: Salt::new()?,
+            slot_salt: Salt::new()?,
+        })
+    }
+
+    // all derived from secret and data, the same data get the same keys
+    fn derive(secret: &[u8], data: &[u8]) -> FileKeys {
+        FileKeys::from_salt(secret, Salt::derive(secret, data))
+    }
+
+    fn from_salt(secret: &[u8], salt: Salt) -> FileKeys {
+        let slot_salt = Salt::derive(secret, salt.get_bytes());
+        let dek = Salt::derive(secret, slot_salt.get_bytes())
+            .get_bytes()
+            .to_vec();
+
+        FileKeys {
+            dek,
+            salt,
+            slot_salt,
+        }
+    }
+
+    // data key of a deterministic file can be computed again from the
+    // secret and the salt in its header
+    fn is_derived(secret: &[u8], salt: &[u8], dek: &[u8]) -> bool {
+        match Salt::from_bytes(salt) {
+            Ok(salt) => FileKeys::from_salt(secret, salt).dek == dek,
+            Err(_) => false,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct FileCrypt<'a> {
     secret: &'a [u8],
+    // data keys wrapped by it instead of secret if set
+    kms: Option<&'a dyn KeyProvider>,
+    // data keys wrapped by it too if set
+    recovery: Option<&'a [u8]>,
+    signer: Option<&'a Ed25519KeyPair>,
+    // public keys, only files signed by them are decrypted if not empty
+    trusted: &'a [Vec<u8>],
+    padding: Padding,
     buffer: Vec<u8>,
 }
 
 impl<'a> FileCrypt<'a> {
-    pub fn new(secret: &'a [u8]) -> FileCrypt {
+    pub fn new(secret: &'a [u8]) -> FileCrypt<'a> {
         let size = BLOCK_SIZE + Crypto::tag_len();
 
         FileCrypt {
             secret,
+            kms: None,
+            recovery: None,
+            signer: None,
+            trusted: &[],
+            padding: Padding::None,
             buffer: vec![0u8; size],
         }
     }
 
-    pub fn encrypt(&mut self, src: &Path, dest: &Path) -> io::Result<()> {
-        let salt = Salt::new()?;
-        let mut crypto = Crypto::new(self.secret, &salt)?;
+    pub fn set_kms(&mut self, kms: &'a dyn KeyProvider) {
+        self.kms = Some(kms);
+    }
+
+    /// add a slot of recovery code to every encrypted entry
+    pub fn set_recovery(&mut self, code: &'a [u8]) {
+        self.recovery = Some(code);
+    }
 
+    /// sign every encrypted entry
+    pub fn set_signer(&mut self, signer: &'a Ed25519KeyPair) {
+        self.signer = Some(signer);
+    }
+
+    /// reject files not signed by one of them
+    pub fn set_trusted(&mut self, trusted: &'a [Vec<u8>]) {
+        self.trusted = trusted;
+    }
+
+    /// pad every encrypted entry, stripped on decrypt
+    pub fn set_padding(&mut self, padding: Padding) {
+        self.padding = padding;
+    }
+
+    pub fn encrypt(&mut self, src: &Path, dest: &Path) -> io::Result<()> {
         let src_f = File::open(src)?;
-        let mut size = src_f.metadata()?.len() as usize;
+        let size = src_f.metadata()?.len() as usize;
         let mut reader = BufReader::new(src_f);
 
         let dest_f = File::create(dest)?;
         let mut writer = BufWriter::new(dest_f);
 
-        // write header metadata
-        writer.write_all(MAGIC)?;
-        writer.write_all(&[VERSION_2])?;
-        writer.write_all(salt.get_bytes())?;
+        self.encrypt_stream(&mut reader, size, &mut writer)?;
+        writer.flush()
+    }
+
+    /// encrypt symlink src itself as a link record, not the file it point to
+    pub fn encrypt_link(&mut self, src: &Path, dest: &Path) -> io::Result<()> {
+        let target = path_to_bytes(&fs::read_link(src)?)?;
+
+        let dest_f = File::create(dest)?;
+        let mut writer = BufWriter::new(dest_f);
 
-        // write placeholder size data
-        let size_start = MAGIC.len() + 1 + Salt::len();
-        let size_len = 8 + Crypto::tag_len();
-        let dest_size = size_start + size_len + crypto_data_size(size);
+        self.encrypt_entry(
+            EntryKind::Symlink,
+            &mut Cursor::new(&target),
+            target.len(),
+            &mut writer,
+            FileKeys::random()?,
+        )?;
+        writer.flush()
+    }
+
+    /// encrypt dir src as a dir record, with its mode if keep_mode
+    pub fn encrypt_dir(&mut self, src: &Path, dest: &Path, keep_mode: bool) -> io::Result<()> {
+        let mut mode = Vec::new();
+        if keep_mode {
+            if let Some(m) = dir_mode(src)? {
+                mode.resize(4, 0);
+                BigEndian::write_u32(&mut mode, m);
+            }
+        }
+
+        let dest_f = File::create(dest)?;
+        let mut writer = BufWriter::new(dest_f);
+
+        self.encrypt_entry(
+            EntryKind::Dir,
+            &mut Cursor::new(&mode),
+            mode.len(),
+            &mut writer,
+            FileKeys::random()?,
+        )?;
+        writer.flush()
+    }
+
+    /// encrypt `size` bytes read from reader, write to writer
+    pub fn encrypt_stream<R: Read, W: Write>(
+        &mut self,
+        reader: &mut R,
+        size: usize,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        self.encrypt_entry(EntryKind::File, reader, size, writer, FileKeys::random()?)
+    }
+
+    /// encrypt data with keys derived from secret and data, the same data
+    /// always get the same output, only equality of data is leaked
+    pub fn encrypt_deterministic<W: Write>(&mut self, data: &[u8], writer: &mut W) -> io::Result<()> {
+        let keys = FileKeys::derive(self.secret, data);
+        self.encrypt_entry(
+            EntryKind::File,
+            &mut Cursor::new(data),
+            data.len(),
+            writer,
+            keys,
+        )
+    }
+
+    fn encrypt_entry<R: Read, W: Write>(
+        &mut self,
+        kind: EntryKind,
+        reader: &mut R,
+        size: usize,
+        writer: &mut W,
+        keys: FileKeys,
+    ) -> io::Result<()> {
+        let mut slots = match self.kms {
+            Some(kms) => vec![KeySlot::kms(kms, &keys.dek)?],
+            None => vec![KeySlot::wrap(SLOT_PASSWORD, self.secret, &keys.dek, &keys.slot_salt)?],
+        };
+        if let Some(code) = self.recovery {
+            let code = recovery::normalize(code);
+            slots.push(KeySlot::wrap(SLOT_RECOVERY, &code, &keys.dek, &Salt::new()?)?);
+        }
+        let mut signed = None;
+        if let Some(signer) = self.signer {
+            let public_key = signer.public_key_bytes().to_vec();
+            slots.push(KeySlot {
+                kind: SLOT_SIGNER,
+                data: public_key.clone(),
+            });
+            signed = Some(Signed::new(public_key));
+        }
+        let mut crypto = Crypto::new(&keys.dek, &keys.salt)?;
+
+        // data, zero padding, then padding length in the final chunk
+        let padded = self.padding != Padding::None;
+        let (mut size, pad) = if padded {
+            let len = size
+                .checked_add(PAD_LEN_SIZE)
+                .ok_or_else(|| io_error("file too large to pad"))?;
+            let padded_size = padded_stream_size(self.padding.size(len)?)?;
+            (padded_size, padded_size - size - PAD_LEN_SIZE)
+        } else {
+            (size, 0)
+        };
+        let mut pad_len = Vec::new();
+        if padded {
+            pad_len.write_u64::<BigEndian>(pad as u64)?;
+        }
+        let data_size = size - pad - pad_len.len();
+        let mut reader = reader
+            .take(data_size as u64)
+            .chain(io::repeat(0).take(pad as u64))
+            .chain(Cursor::new(pad_len));
+        let empty = size == 0;
+
+        // write header metadata, slots not signed
+        write_signed(writer, &mut signed, MAGIC)?;
+        write_signed(writer, &mut signed, &[VERSION_4])?;
+        write_slots(writer, &slots)?;
+        write_signed(writer, &mut signed, keys.salt.get_bytes())?;
+
+        // write size and kind
+        let size_start = MAGIC.len() + 1 + slots_len(&slots) + Salt::len();
+        let size_len = 8 + 1 + Crypto::tag_len();
+        let sign_len = if signed.is_some() { SIGNATURE_LEN } else { 0 };
+        let dest_size = size_start + size_len + crypto_data_size(size) + sign_len;
 
         BigEndian::write_u64(&mut self.buffer, dest_size as u64);
-        let len = crypto.encrypt(&mut self.buffer, 8)?;
-        writer.write_all(&self.buffer[..len])?;
+        self.buffer[8] = kind.to_u8() | if padded { KIND_PADDED } else { 0 };
+        let len = crypto.encrypt(&mut self.buffer, 8 + 1)?;
+        write_signed(writer, &mut signed, &self.buffer[..len])?;
 
         loop {
             match reader.read_exact(&mut self.buffer[..BLOCK_SIZE]) {
                 Ok(()) => {
                     let len = crypto.encrypt(&mut self.buffer, BLOCK_SIZE)?;
-                    writer.write_all(&self.buffer[..len])?;
+                    write_signed(writer, &mut signed, &self.buffer[..len])?;
                     size -= BLOCK_SIZE;
                 }
                 Err(e) => if e.kind() == io::ErrorKind::UnexpectedEof {
-                    if size != 0 {
+                    // empty data still has one chunk, see crypto_data_size
+                    if size != 0 || empty {
                         let len = crypto.encrypt(&mut self.buffer, size)?;
-                        writer.write_all(&self.buffer[..len])?;
+                        write_signed(writer, &mut signed, &self.buffer[..len])?;
                     }
                     break;
                 } else {
@@ -92,17 +521,239 @@ impl<'a> FileCrypt<'a> {
             }
         }
 
+        if let (Some(signer), Some(signed)) = (self.signer, signed) {
+            writer.write_all(signer.sign(signed.ctx.finish().as_ref()).as_ref())?;
+        }
+
         Ok(())
     }
 
-    pub fn decrypt(&mut self, src: &Path, dest: &Path) -> io::Result<()> {
+    /// re-encrypt src with new secret to dest, decrypted data only in memory
+    pub fn rekey(&mut self, new_secret: &[u8], src: &Path, dest: &Path) -> io::Result<()> {
         let src_f = File::open(src)?;
-        let mut size = src_f.metadata()?.len() as usize;
+        let size = src_f.metadata()?.len() as usize;
         let mut reader = BufReader::new(src_f);
 
+        let (crypto, header) = self.read_header(&mut reader, size)?;
+        if header.padded {
+            return Err(io_error("padded file can not be re-encrypted"));
+        }
+        let data_size = header.data_len;
+        let plain_size = plain_data_size(data_size)?;
+        let mut plain = DecryptReader::new(crypto, &mut reader, data_size);
+
         let dest_f = File::create(dest)?;
         let mut writer = BufWriter::new(dest_f);
 
+        // new key slots by password only, padded if asked
+        let mut new_crypt = FileCrypt::new(new_secret);
+        new_crypt.set_padding(self.padding);
+        new_crypt.encrypt_entry(
+            header.kind,
+            &mut plain,
+            plain_size,
+            &mut writer,
+            FileKeys::random()?,
+        )?;
+        writer.flush()
+    }
+
+    /// replace password slots the secret can open with ones of new secret,
+    /// all of them if opened by recovery code, write it to dest. data is
+    /// copied as is. returns false if src is not an envelope file or its
+    /// data key is derived from the secret (deterministic), which needs a
+    /// full rekey
+    pub fn rewrap(&mut self, new_secret: &[u8], src: &Path, dest: &Path) -> io::Result<bool> {
+        let mut reader = BufReader::new(File::open(src)?);
+
+        let mut head = [0u8; 6];
+        match reader.read_exact(&mut head[..MAGIC.len() + 1]) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        if probe_data_version(&head) != Some(VERSION_4) {
+            return Ok(false);
+        }
+
+        let slots = read_slots(&mut reader)?;
+        let dek = self.open_slots(&slots)?;
+
+        // slots of other keys are kept, recovery code reset the password
+        let by_recovery = !slots
+            .iter()
+            .any(|slot| slot.kind == SLOT_PASSWORD && slot.open(self.secret).is_some());
+        let mut new_slots = Vec::with_capacity(slots.len());
+        let mut replaced = 0;
+        for slot in &slots {
+            if slot.kind == SLOT_PASSWORD && (by_recovery || slot.open(self.secret).is_some()) {
+                let new_slot = KeySlot::wrap(SLOT_PASSWORD, new_secret, &dek, &Salt::new()?)?;
+                new_slots.push(new_slot);
+                replaced += 1;
+            } else {
+                new_slots.push(slot.clone());
+            }
+        }
+        if replaced == 0 {
+            return Err(io_error("no password key slot to rekey"));
+        }
+
+        // old secret could derive the data key again, not only unwrap it
+        let mut salt = vec![0u8; Salt::len()];
+        reader.read_exact(&mut salt)?;
+        if FileKeys::is_derived(self.secret, &salt, &dek) {
+            return Ok(false);
+        }
+
+        // signature not cover slots, still valid
+        let mut writer = BufWriter::new(File::create(dest)?);
+        writer.write_all(&head[..MAGIC.len() + 1])?;
+        write_slots(&mut writer, &new_slots)?;
+        writer.write_all(&salt)?;
+        io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+
+        Ok(true)
+    }
+
+    /// true if secret or kms can open key of path, only header is read
+    pub fn can_open(&mut self, path: &Path) -> bool {
+        let size = match fs::symlink_metadata(path) {
+            Ok(meta) => meta.len() as usize,
+            Err(_) => return false,
+        };
+        match File::open(path) {
+            Ok(f) => self.read_header(&mut BufReader::new(f), size).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// what is lost if path is encrypted again by password only, slots
+    /// of other keys, signature and padding. empty if nothing
+    pub fn password_only_loss(&mut self, path: &Path) -> io::Result<Vec<&'static str>> {
+        let f = File::open(path)?;
+        let size = f.metadata()?.len() as usize;
+        let mut reader = BufReader::new(f);
+        let mut loss = Vec::new();
+
+        let mut head = [0u8; 6];
+        reader.read_exact(&mut head[..MAGIC.len() + 1])?;
+        if probe_data_version(&head) == Some(VERSION_4) {
+            let slots = read_slots(&mut reader)?;
+            for &(kind, name) in &[
+                (SLOT_KMS, "kms key slot"),
+                (SLOT_RECOVERY, "recovery code slot"),
+                (SLOT_SIGNER, "signature"),
+            ] {
+                if slots.iter().any(|slot| slot.kind == kind) {
+                    loss.push(name);
+                }
+            }
+        }
+
+        // padding flag is encrypted, read the header again
+        reader.seek(SeekFrom::Start(0))?;
+        let (_, header) = self.read_header(&mut reader, size)?;
+        if header.padded {
+            loss.push("padding");
+        }
+
+        Ok(loss)
+    }
+
+    // data key from the first slot secret or kms can open
+    fn open_slots(&self, slots: &[KeySlot]) -> io::Result<Vec<u8>> {
+        for slot in slots {
+            match (slot.kind, self.kms) {
+                (SLOT_PASSWORD, _) | (SLOT_RECOVERY, _) => if let Some(dek) = slot.open(self.secret) {
+                    return Ok(dek);
+                },
+                // kms error is more useful than wrong password
+                (SLOT_KMS, Some(kms)) => return kms.unwrap_key(&slot.data),
+                _ => {}
+            }
+        }
+
+        if slots.iter().any(|slot| slot.kind == SLOT_KMS) && self.kms.is_none() {
+            return Err(io_error("data key wrapped by kms, need --kms"));
+        }
+        Err(io_error("no key slot can be opened, wrong password?"))
+    }
+
+    /// decrypt src to dest, link record will be restored as symlink,
+    /// dir record will be restored as dir
+    pub fn decrypt(&mut self, src: &Path, dest: &Path) -> io::Result<()> {
+        let src_f = File::open(src)?;
+        let size = src_f.metadata()?.len() as usize;
+        let mut reader = BufReader::new(src_f);
+
+        let (mut crypto, header) = self.read_header(&mut reader, size)?;
+
+        match header.kind {
+            EntryKind::File => {
+                // signature is checked at the end, dest only appears after it
+                let tmp = tmp_path(dest);
+                let result = File::create(&tmp).and_then(|dest_f| {
+                    let mut writer = BufWriter::new(dest_f);
+                    self.decrypt_chunks(&mut crypto, &mut reader, header, &mut writer)?;
+                    writer.flush()
+                });
+                if result.is_err() {
+                    let _ = fs::remove_file(&tmp);
+                    return result;
+                }
+                fs::rename(&tmp, dest)
+            }
+            EntryKind::Symlink => {
+                let mut target = Vec::new();
+                self.decrypt_chunks(&mut crypto, &mut reader, header, &mut target)?;
+                let target = bytes_to_path(target)?;
+                // link out of dest lets later files be written through it
+                if !is_inner_link(&target) {
+                    return Err(io_error(&format!(
+                        "link target {:?} maybe out of dest, refused",
+                        target
+                    )));
+                }
+                create_symlink(&target, dest)
+            }
+            EntryKind::Dir => {
+                let mut mode = Vec::new();
+                self.decrypt_chunks(&mut crypto, &mut reader, header, &mut mode)?;
+
+                fs::create_dir_all(dest)?;
+                if mode.len() == 4 {
+                    set_dir_mode(dest, BigEndian::read_u32(&mode))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// decrypt `size` bytes encrypted data read from reader, write to writer,
+    /// link and dir records are refused. signed data is kept in memory
+    /// until its signature checked
+    pub fn decrypt_stream<R: Read, W: Write>(
+        &mut self,
+        reader: &mut R,
+        size: usize,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        let (mut crypto, header) = self.read_header(reader, size)?;
+        if header.kind != EntryKind::File {
+            return Err(io_error(&format!("{:?} record is not a file", header.kind)));
+        }
+
+        // signature is at the end, nothing written before it is checked
+        if header.signed.is_some() {
+            let mut plain = Vec::new();
+            self.decrypt_chunks(&mut crypto, reader, header, &mut plain)?;
+            return writer.write_all(&plain);
+        }
+        self.decrypt_chunks(&mut crypto, reader, header, writer)
+    }
+
+    fn read_header<R: Read>(&mut self, reader: &mut R, size: usize) -> io::Result<(Crypto, Header)> {
         reader.read_exact(&mut self.buffer[..MAGIC.len()])?;
         if &self.buffer[..MAGIC.len()] != MAGIC {
             return Err(io_error("magic not match"));
@@ -110,17 +761,64 @@ impl<'a> FileCrypt<'a> {
 
         let mut version = [0u8];
         reader.read_exact(&mut version)?;
-        if version[0] != VERSION_1 && version[0] != VERSION_2 {
-            return Err(io_error(&format!("version '{}' not support", version[0])));
+        let (header_len, dek, signer) = match version[0] {
+            VERSION_1 => (MAGIC.len() + 1 + Salt::len(), None, None),
+            VERSION_2 => (
+                MAGIC.len() + 1 + Salt::len() + 8 + Crypto::tag_len(),
+                None,
+                None,
+            ),
+            VERSION_3 => (
+                MAGIC.len() + 1 + Salt::len() + 8 + 1 + Crypto::tag_len(),
+                None,
+                None,
+            ),
+            VERSION_4 => {
+                let slots = read_slots(reader)?;
+                let signer = find_signer(&slots);
+                // untrusted file is rejected before its key is asked
+                self.check_signer(signer.as_ref())?;
+                let dek = self.open_slots(&slots)?;
+                let len = MAGIC.len() + 1 + slots_len(&slots) + Salt::len() + 8 + 1
+                    + Crypto::tag_len();
+                (len, Some(dek), signer)
+            }
+            _ => return Err(io_error(&format!("version '{}' not support", version[0]))),
+        };
+        if signer.is_none() {
+            self.check_signer(None)?;
+        }
+
+        let sign_len = if signer.is_some() { SIGNATURE_LEN } else { 0 };
+        if size < header_len + sign_len {
+            return Err(io_error("file too small"));
+        }
+
+        let mut signed = signer.map(Signed::new);
+        if let Some(ref mut signed) = signed {
+            signed.ctx.update(MAGIC);
+            signed.ctx.update(&version);
         }
 
         reader.read_exact(&mut self.buffer[..Salt::len()])?;
+        if let Some(ref mut signed) = signed {
+            signed.ctx.update(&self.buffer[..Salt::len()]);
+        }
         let salt = Salt::from_bytes(&self.buffer[..Salt::len()])?;
-        let mut crypto = Crypto::new(self.secret, &salt)?;
+        let mut crypto = match dek {
+            Some(ref dek) => Crypto::new(dek, &salt)?,
+            None => Crypto::new(self.secret, &salt)?,
+        };
 
-        if version[0] == VERSION_2 {
-            let size_len = 8 + Crypto::tag_len();
+        let mut kind = EntryKind::File;
+        let mut padded = false;
+        if version[0] != VERSION_1 {
+            let meta_len = if version[0] == VERSION_2 { 8 } else { 8 + 1 };
+            let size_len = meta_len + Crypto::tag_len();
             reader.read_exact(&mut self.buffer[..size_len])?;
+            if let Some(ref mut signed) = signed {
+                signed.ctx.update(&self.buffer[..size_len]);
+            }
             crypto.decrypt(&mut self.buffer[..size_len])?;
 
             let mut rdr = Cursor::new(&self.buffer[..8]);
@@ -131,39 +829,390 @@ impl<'a> FileCrypt<'a> {
                     size, len
                 )));
             }
+
+            if version[0] == VERSION_3 {
+                kind = EntryKind::from_u8(self.buffer[8])?;
+            }
+            if version[0] == VERSION_4 {
+                kind = EntryKind::from_u8(self.buffer[8] & !KIND_PADDED)?;
+                padded = self.buffer[8] & KIND_PADDED != 0;
+            }
         }
 
-        let header_len = match version[0] {
-            VERSION_1 => MAGIC.len() + 1 + Salt::len(),
-            VERSION_2 => MAGIC.len() + 1 + Salt::len() + 8 + Crypto::tag_len(),
-            _ => unreachable!(),
+        Ok((
+            crypto,
+            Header {
+                kind,
+                data_len: size - header_len - sign_len,
+                padded,
+                signed,
+            },
+        ))
+    }
+
+    // with trusted signers, only files signed by one of them are accepted
+    fn check_signer(&self, signer: Option<&Vec<u8>>) -> io::Result<()> {
+        if self.trusted.is_empty() {
+            return Ok(());
+        }
+
+        match signer {
+            None => Err(io_error("file not signed")),
+            Some(key) if self.trusted.contains(key) => Ok(()),
+            Some(key) => Err(io_error(&format!(
+                "signer '{}' not trusted",
+                base64::encode(key)
+            ))),
+        }
+    }
+
+    // decrypt data after header, then check signature after data if signed
+    fn decrypt_chunks<R: Read, W: Write>(
+        &mut self,
+        crypto: &mut Crypto,
+        reader: &mut R,
+        header: Header,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        let mut signed = header.signed;
+        let mut size = header.data_len;
+        let mut unpad = if header.padded {
+            Some(Unpadder::default())
+        } else {
+            None
         };
-        size -= header_len;
 
-        loop {
-            match reader.read_exact(&mut self.buffer) {
-                Ok(()) => {
-                    let len = crypto.decrypt(&mut self.buffer)?;
-                    writer.write_all(&self.buffer[..len])?;
-                    size -= self.buffer.len();
-                }
-                Err(e) => if e.kind() == io::ErrorKind::UnexpectedEof {
-                    if size != 0 {
-                        let len = crypto.decrypt(&mut self.buffer[..size])?;
-                        writer.write_all(&self.buffer[..len])?;
-                    }
+        while size > 0 {
+            let n = cmp::min(size, self.buffer.len());
+            reader.read_exact(&mut self.buffer[..n])?;
+            if let Some(ref mut signed) = signed {
+                signed.ctx.update(&self.buffer[..n]);
+            }
 
-                    break;
-                } else {
-                    return Err(e);
-                },
+            let len = crypto.decrypt(&mut self.buffer[..n])?;
+            size -= n;
+            match unpad {
+                Some(ref mut unpad) => unpad.write(writer, &self.buffer[..len], size == 0)?,
+                None => writer.write_all(&self.buffer[..len])?,
             }
         }
+        if let Some(unpad) = unpad {
+            unpad.check()?;
+        }
+
+        match signed {
+            Some(signed) => signed.verify(reader),
+            None => Ok(()),
+        }
+    }
+
+    /// decrypt cipher file and compare with plain file, make sure they are same
+    pub fn verify(&mut self, plain: &Path, cipher: &Path) -> io::Result<()> {
+        let cipher_f = File::open(cipher)?;
+        let size = cipher_f.metadata()?.len() as usize;
+        let mut reader = BufReader::new(cipher_f);
+
+        let mut cmp = CompareWriter {
+            reader: BufReader::new(File::open(plain)?),
+            buffer: Vec::new(),
+        };
+        self.decrypt_stream(&mut reader, size, &mut cmp)?;
+
+        // plain file should have no more data
+        if cmp.reader.read(&mut [0u8; 1])? != 0 {
+            return Err(io_error("verify content not match"));
+        }
+
+        Ok(())
+    }
+}
+
+// strip zero padding and its length at the end of decrypted chunks.
+// zeros at the end of a chunk are only counted until more data comes,
+// so padding over many chunks needs no memory
+#[derive(Default)]
+struct Unpadder {
+    zeros: u64,
+    done: bool,
+}
+
+impl Unpadder {
+    fn write<W: Write>(&mut self, writer: &mut W, chunk: &[u8], last: bool) -> io::Result<()> {
+        let data = if last {
+            if chunk.len() < PAD_LEN_SIZE {
+                return Err(io_error("padding length not found"));
+            }
+            &chunk[..chunk.len() - PAD_LEN_SIZE]
+        } else {
+            chunk
+        };
+
+        let zeros = data.iter().rev().take_while(|&&b| b == 0).count();
+        if zeros < data.len() {
+            self.write_zeros(writer, self.zeros)?;
+            writer.write_all(&data[..data.len() - zeros])?;
+            self.zeros = 0;
+        }
+        self.zeros += zeros as u64;
+
+        if last {
+            let pad = BigEndian::read_u64(&chunk[chunk.len() - PAD_LEN_SIZE..]);
+            if pad > self.zeros {
+                return Err(io_error("padding not valid"));
+            }
+            let zeros = self.zeros - pad;
+            self.write_zeros(writer, zeros)?;
+            self.done = true;
+        }
+        Ok(())
+    }
+
+    fn write_zeros<W: Write>(&self, writer: &mut W, n: u64) -> io::Result<()> {
+        io::copy(&mut io::repeat(0).take(n), writer).map(|_| ())
+    }
+
+    fn check(&self) -> io::Result<()> {
+        if self.done {
+            Ok(())
+        } else {
+            Err(io_error("padding length not found"))
+        }
+    }
+}
+
+// a reader read encrypted chunks from inner reader, and decrypt on demand
+struct DecryptReader<'r, R: 'r> {
+    crypto: Crypto,
+    reader: &'r mut R,
+    // encrypted data left in reader
+    left: usize,
+    buffer: Vec<u8>,
+    pos: usize,
+    len: usize,
+}
+
+impl<'r, R: Read> DecryptReader<'r, R> {
+    fn new(crypto: Crypto, reader: &'r mut R, size: usize) -> DecryptReader<'r, R> {
+        DecryptReader {
+            crypto,
+            reader,
+            left: size,
+            buffer: vec![0u8; BLOCK_SIZE + Crypto::tag_len()],
+            pos: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<'r, R: Read> Read for DecryptReader<'r, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.len {
+            if self.left == 0 {
+                return Ok(0);
+            }
+
+            let n = cmp::min(self.left, self.buffer.len());
+            self.reader.read_exact(&mut self.buffer[..n])?;
+            self.len = self.crypto.decrypt(&mut self.buffer[..n])?;
+            self.pos = 0;
+            self.left -= n;
+        }
+
+        let n = cmp::min(buf.len(), self.len - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+// a writer compare written data with data read from reader
+struct CompareWriter<R> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> Write for CompareWriter<R> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.resize(data.len(), 0);
+        self.reader
+            .read_exact(&mut self.buffer)
+            .map_err(|_| io_error("verify content not match"))?;
+
+        if self.buffer[..] != data[..] {
+            return Err(io_error("verify content not match"));
+        }
 
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
         Ok(())
     }
 }
 
+/// check signature of encrypted file without decrypt it, returns public
+/// key of the signer
+pub fn verify_signature(path: &Path) -> io::Result<Vec<u8>> {
+    let f = File::open(path)?;
+    let size = f.metadata()?.len() as usize;
+    let mut reader = BufReader::new(f);
+
+    let mut head = [0u8; 6];
+    reader.read_exact(&mut head[..MAGIC.len() + 1])?;
+    if probe_data_version(&head) != Some(VERSION_4) {
+        return Err(io_error("file not signed"));
+    }
+
+    let slots = read_slots(&mut reader)?;
+    let public_key = find_signer(&slots).ok_or_else(|| io_error("file not signed"))?;
+    let slots_end = MAGIC.len() + 1 + slots_len(&slots);
+    let header_len = slots_end + Salt::len() + 8 + 1 + Crypto::tag_len();
+    if size < header_len + SIGNATURE_LEN {
+        return Err(io_error("file too small"));
+    }
+
+    let mut signed = Signed::new(public_key.clone());
+    signed.ctx.update(&head[..MAGIC.len() + 1]);
+
+    let mut buffer = vec![0u8; BLOCK_SIZE];
+    let mut left = size - slots_end - SIGNATURE_LEN;
+    while left > 0 {
+        let n = cmp::min(left, buffer.len());
+        reader.read_exact(&mut buffer[..n])?;
+        signed.ctx.update(&buffer[..n]);
+        left -= n;
+    }
+    signed.verify(&mut reader)?;
+
+    Ok(public_key)
+}
+
+/// hash of envelope file header except key slots, it changes when the
+/// file is re-encrypted, not when rekeyed
+pub fn header_hash(path: &Path) -> io::Result<Vec<u8>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut head = [0u8; 6];
+    reader.read_exact(&mut head[..MAGIC.len() + 1])?;
+    if probe_data_version(&head) != Some(VERSION_4) {
+        return Err(io_error("not in the latest version"));
+    }
+    read_slots(&mut reader)?;
+
+    let mut meta = vec![0u8; Salt::len() + 8 + 1 + Crypto::tag_len()];
+    reader.read_exact(&mut meta)?;
+
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(&head[..MAGIC.len() + 1]);
+    ctx.update(&meta);
+    Ok(ctx.finish().as_ref().to_vec())
+}
+
+/// quick check file header before decrypt, returns format version if
+/// file starts with magic, None if not encrypted by eakio
+pub fn probe_version(path: &Path) -> io::Result<Option<u8>> {
+    let mut buf = [0u8; 6];
+    let mut f = File::open(path)?;
+
+    match f.read_exact(&mut buf[..MAGIC.len() + 1]) {
+        Ok(()) => Ok(probe_data_version(&buf)),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// like probe_version, check the head of data in memory
+pub fn probe_data_version(data: &[u8]) -> Option<u8> {
+    if data.len() > MAGIC.len() && &data[..MAGIC.len()] == MAGIC {
+        Some(data[MAGIC.len()])
+    } else {
+        None
+    }
+}
+
+#[inline]
+pub fn is_supported_version(version: u8) -> bool {
+    version == VERSION_1 || version == VERSION_2 || version == VERSION_3 || version == VERSION_4
+}
+
+/// VERSION_1 has no authenticated size, truncation at chunk boundary
+/// can not be detected
+#[inline]
+pub fn is_legacy_version(version: u8) -> bool {
+    version == VERSION_1
+}
+
+#[inline]
+pub fn is_latest_version(version: u8) -> bool {
+    version == VERSION_4
+}
+
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> io::Result<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(path.as_os_str().as_bytes().to_vec())
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &Path) -> io::Result<Vec<u8>> {
+    path.to_str()
+        .map(|s| s.as_bytes().to_vec())
+        .ok_or_else(|| io_error(&format!("path {:?} not valid utf-8", path)))
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|e| io_error(&format!("{}", e)))
+}
+
+// relative target without '..', always inside the link dir
+pub fn is_inner_link(target: &Path) -> bool {
+    target
+        .components()
+        .all(|comp| matches!(comp, Component::Normal(_) | Component::CurDir))
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, dest: &Path) -> io::Result<()> {
+    ::std::os::unix::fs::symlink(target, dest)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, dest: &Path) -> io::Result<()> {
+    ::std::os::windows::fs::symlink_file(target, dest)
+}
+
+#[cfg(unix)]
+fn dir_mode(path: &Path) -> io::Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(Some(fs::metadata(path)?.permissions().mode()))
+}
+
+#[cfg(not(unix))]
+fn dir_mode(_path: &Path) -> io::Result<Option<u32>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn set_dir_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_dir_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
 // calc crypto in_size data out size
 fn crypto_data_size(in_size: usize) -> usize {
     let nblock = if in_size == 0 {
@@ -177,6 +1226,35 @@ fn crypto_data_size(in_size: usize) -> usize {
     in_size + tag_size
 }
 
+// padding length must be whole in the final chunk, not split by chunks
+fn padded_stream_size(size: usize) -> io::Result<usize> {
+    let tail = size % BLOCK_SIZE;
+    if tail != 0 && tail < PAD_LEN_SIZE {
+        size.checked_add(PAD_LEN_SIZE - tail)
+            .ok_or_else(|| io_error("file too large to pad"))
+    } else {
+        Ok(size)
+    }
+}
+
+// calc crypto out_size data in size, reverse of crypto_data_size
+fn plain_data_size(out_size: usize) -> io::Result<usize> {
+    // VERSION_1 empty file has no chunk
+    if out_size == 0 {
+        return Ok(0);
+    }
+
+    let chunk_size = BLOCK_SIZE + Crypto::tag_len();
+    let nblock = (out_size - 1) / chunk_size + 1;
+    let tag_size = nblock * Crypto::tag_len();
+
+    if out_size < tag_size {
+        return Err(io_error("encrypted data size not valid"));
+    }
+
+    Ok(out_size - tag_size)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -211,4 +1289,407 @@ mod test {
         let in_size4 = 7 * in_size2;
         assert_eq!(7 * out_size2, crypto_data_size(in_size4));
     }
+
+    #[test]
+    fn test_plain_data_size() {
+        for &size in &[0, 1, BLOCK_SIZE - 1, BLOCK_SIZE, BLOCK_SIZE + 1, 5 * BLOCK_SIZE] {
+            assert_eq!(size, plain_data_size(crypto_data_size(size)).unwrap());
+        }
+
+        assert!(plain_data_size(Crypto::tag_len() - 1).is_err());
+    }
+
+    #[test]
+    fn test_decrypt_reader() {
+        let secret = [2u8; 16];
+        let mut file_crypt = FileCrypt::new(&secret);
+
+        for &size in &[0, 7, 2 * BLOCK_SIZE + 9] {
+            let plain: Vec<u8> = (0..size).map(|i| (i % 13) as u8).collect();
+            let mut cipher = Vec::new();
+            file_crypt
+                .encrypt_stream(&mut Cursor::new(&plain), size, &mut cipher)
+                .unwrap();
+
+            let mut rdr = Cursor::new(&cipher);
+            let (crypto, header) = file_crypt.read_header(&mut rdr, cipher.len()).unwrap();
+            let data_size = header.data_len;
+            assert_eq!(size, plain_data_size(data_size).unwrap());
+
+            let mut out = Vec::new();
+            DecryptReader::new(crypto, &mut rdr, data_size)
+                .read_to_end(&mut out)
+                .unwrap();
+            assert_eq!(plain, out);
+        }
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        let secret = [1u8; 16];
+        let mut file_crypt = FileCrypt::new(&secret);
+
+        for &size in &[0, 1, BLOCK_SIZE, BLOCK_SIZE + 7, 3 * BLOCK_SIZE] {
+            let plain: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+
+            let mut cipher = Vec::new();
+            file_crypt
+                .encrypt_stream(&mut Cursor::new(&plain), size, &mut cipher)
+                .unwrap();
+
+            let mut out = Vec::new();
+            file_crypt
+                .decrypt_stream(&mut Cursor::new(&cipher), cipher.len(), &mut out)
+                .unwrap();
+            assert_eq!(plain, out);
+
+            // truncated
+            let mut out = Vec::new();
+            assert!(
+                file_crypt
+                    .decrypt_stream(&mut Cursor::new(&cipher), cipher.len() - 1, &mut out)
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_padding() {
+        let secret = [1u8; 16];
+        let mut file_crypt = FileCrypt::new(&secret);
+        let mut plain_crypt = FileCrypt::new(&secret);
+
+        for &padding in &[Padding::Padme, Padding::Block(4096), Padding::Block(3 * BLOCK_SIZE)] {
+            file_crypt.set_padding(padding);
+            let mut sizes = Vec::new();
+
+            for &size in &[0, 1, 4000, BLOCK_SIZE - 3, BLOCK_SIZE, 2 * BLOCK_SIZE + 1] {
+                // zeros at the end are data, not padding
+                let mut plain: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+                for b in plain.iter_mut().rev().take(5000) {
+                    *b = 0;
+                }
+
+                let mut cipher = Vec::new();
+                file_crypt
+                    .encrypt_stream(&mut Cursor::new(&plain), size, &mut cipher)
+                    .unwrap();
+                let mut unpadded = Vec::new();
+                plain_crypt
+                    .encrypt_stream(&mut Cursor::new(&plain), size, &mut unpadded)
+                    .unwrap();
+                assert!(cipher.len() > unpadded.len());
+                sizes.push(cipher.len());
+
+                let mut out = Vec::new();
+                plain_crypt
+                    .decrypt_stream(&mut Cursor::new(&cipher), cipher.len(), &mut out)
+                    .unwrap();
+                assert_eq!(plain, out);
+            }
+
+            // one size for all in a block
+            if let Padding::Block(_) = padding {
+                assert_eq!(sizes[0], sizes[2]);
+            }
+        }
+
+        file_crypt.set_padding(Padding::Block(4096));
+        let mut cipher = Vec::new();
+        file_crypt
+            .encrypt_stream(&mut Cursor::new(b"hello"), 5, &mut cipher)
+            .unwrap();
+        let mut other = Vec::new();
+        file_crypt
+            .encrypt_stream(&mut Cursor::new(b"hello kelsi"), 11, &mut other)
+            .unwrap();
+        assert_eq!(cipher.len(), other.len());
+    }
+
+    #[test]
+    fn test_encrypt_deterministic() {
+        let secret = [1u8; 16];
+        let mut file_crypt = FileCrypt::new(&secret);
+        let plain = b"hello kelsi";
+
+        let mut cipher1 = Vec::new();
+        let mut cipher2 = Vec::new();
+        file_crypt
+            .encrypt_deterministic(plain, &mut cipher1)
+            .unwrap();
+        file_crypt
+            .encrypt_deterministic(plain, &mut cipher2)
+            .unwrap();
+        assert_eq!(cipher1, cipher2);
+        assert_eq!(probe_data_version(&cipher1), Some(VERSION_4));
+
+        let mut out = Vec::new();
+        file_crypt
+            .decrypt_stream(&mut Cursor::new(&cipher1), cipher1.len(), &mut out)
+            .unwrap();
+        assert_eq!(&out[..], &plain[..]);
+    }
+
+    #[test]
+    fn test_rekey_deterministic() {
+        use std::env;
+        use std::process;
+
+        let old = [1u8; 16];
+        let new = [2u8; 16];
+        let plain = b"hello kelsi";
+        let path = env::temp_dir().join(format!("eakio-test-rekey-det-{}", process::id()));
+        let dest = env::temp_dir().join(format!("eakio-test-rekey-det-dest-{}", process::id()));
+
+        // data key derived again by the secret and salt in header, then
+        // wrapped in a new password slot
+        let forge = |cipher: &[u8], secret: &[u8]| -> Vec<u8> {
+            let slots_end = MAGIC.len() + 1 + slots_len(&read_slots(&mut &cipher[6..]).unwrap());
+            let salt = &cipher[slots_end..slots_end + Salt::len()];
+            let keys = FileKeys::from_salt(secret, Salt::from_bytes(salt).unwrap());
+            let slot = KeySlot::wrap(SLOT_PASSWORD, secret, &keys.dek, &keys.slot_salt).unwrap();
+
+            let mut forged = cipher[..MAGIC.len() + 1].to_vec();
+            write_slots(&mut forged, &[slot]).unwrap();
+            forged.extend_from_slice(&cipher[slots_end..]);
+            forged
+        };
+
+        let mut cipher = Vec::new();
+        FileCrypt::new(&old)
+            .encrypt_deterministic(plain, &mut cipher)
+            .unwrap();
+        let forged = forge(&cipher, &old);
+        let mut out = Vec::new();
+        FileCrypt::new(&old)
+            .decrypt_stream(&mut Cursor::new(&forged), forged.len(), &mut out)
+            .unwrap();
+        assert_eq!(&out[..], &plain[..]);
+
+        // slots only rewrap not enough, full rekey
+        fs::write(&path, &cipher).unwrap();
+        assert!(!FileCrypt::new(&old).rewrap(&new, &path, &dest).unwrap());
+        FileCrypt::new(&old).rekey(&new, &path, &dest).unwrap();
+        let rekeyed = fs::read(&dest).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&dest).unwrap();
+
+        let mut out = Vec::new();
+        FileCrypt::new(&new)
+            .decrypt_stream(&mut Cursor::new(&rekeyed), rekeyed.len(), &mut out)
+            .unwrap();
+        assert_eq!(&out[..], &plain[..]);
+
+        let forged = forge(&rekeyed, &old);
+        let mut out = Vec::new();
+        assert!(
+            FileCrypt::new(&old)
+                .decrypt_stream(&mut Cursor::new(&forged), forged.len(), &mut out)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_rewrap() {
+        use std::env;
+        use std::process;
+
+        let old = [1u8; 16];
+        let new = [2u8; 16];
+        let plain: Vec<u8> = (0..BLOCK_SIZE + 7).map(|i| (i % 251) as u8).collect();
+        let path = env::temp_dir().join(format!("eakio-test-rewrap-{}", process::id()));
+        let dest = env::temp_dir().join(format!("eakio-test-rewrap-dest-{}", process::id()));
+
+        let mut cipher = Vec::new();
+        FileCrypt::new(&old)
+            .encrypt_stream(&mut Cursor::new(&plain), plain.len(), &mut cipher)
+            .unwrap();
+        fs::write(&path, &cipher).unwrap();
+
+        assert!(FileCrypt::new(&new).rewrap(&old, &path, &dest).is_err());
+        assert!(FileCrypt::new(&old).rewrap(&new, &path, &dest).unwrap());
+
+        // only slots changed
+        let rewrapped = fs::read(&dest).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&dest).unwrap();
+        assert_eq!(cipher.len(), rewrapped.len());
+        let slots_end = MAGIC.len() + 1 + slots_len(&read_slots(&mut &cipher[6..]).unwrap());
+        assert_ne!(cipher[..slots_end], rewrapped[..slots_end]);
+        assert_eq!(cipher[slots_end..], rewrapped[slots_end..]);
+
+        let mut out = Vec::new();
+        FileCrypt::new(&new)
+            .decrypt_stream(&mut Cursor::new(&rewrapped), rewrapped.len(), &mut out)
+            .unwrap();
+        assert_eq!(plain, out);
+
+        let mut out = Vec::new();
+        assert!(
+            FileCrypt::new(&old)
+                .decrypt_stream(&mut Cursor::new(&rewrapped), rewrapped.len(), &mut out)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_recovery_slot() {
+        use std::env;
+        use std::process;
+
+        let secret = [1u8; 16];
+        let new = [2u8; 16];
+        let code = b"ABCD-EFGH-JKMN-PQRS";
+        let plain = b"hello kelsi";
+        let path = env::temp_dir().join(format!("eakio-test-recovery-{}", process::id()));
+
+        let mut file_crypt = FileCrypt::new(&secret);
+        file_crypt.set_recovery(code);
+        let mut cipher = Vec::new();
+        file_crypt
+            .encrypt_stream(&mut Cursor::new(plain), plain.len(), &mut cipher)
+            .unwrap();
+
+        // code as typed, case and separators not matter
+        for typed in &[&code[..], b"abcd efgh jkmn pqrs", b"ABCDEFGHJKMNPQRS"] {
+            let mut out = Vec::new();
+            FileCrypt::new(typed)
+                .decrypt_stream(&mut Cursor::new(&cipher), cipher.len(), &mut out)
+                .unwrap();
+            assert_eq!(&out[..], &plain[..]);
+        }
+
+        // code reset the password, and still works
+        fs::write(&path, &cipher).unwrap();
+        let dest = path.with_extension("dest");
+        assert!(FileCrypt::new(code).rewrap(&new, &path, &dest).unwrap());
+        let rewrapped = fs::read(&dest).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&dest).unwrap();
+
+        for key in &[&new[..], &code[..]] {
+            let mut out = Vec::new();
+            FileCrypt::new(key)
+                .decrypt_stream(&mut Cursor::new(&rewrapped), rewrapped.len(), &mut out)
+                .unwrap();
+            assert_eq!(&out[..], &plain[..]);
+        }
+        let mut out = Vec::new();
+        assert!(
+            FileCrypt::new(&secret)
+                .decrypt_stream(&mut Cursor::new(&rewrapped), rewrapped.len(), &mut out)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_password_only_loss() {
+        use std::env;
+        use std::process;
+
+        let secret = [1u8; 16];
+        let plain = b"hello kelsi";
+        let path = env::temp_dir().join(format!("eakio-test-loss-{}", process::id()));
+
+        let mut file_crypt = FileCrypt::new(&secret);
+        let mut cipher = Vec::new();
+        file_crypt
+            .encrypt_stream(&mut Cursor::new(plain), plain.len(), &mut cipher)
+            .unwrap();
+        fs::write(&path, &cipher).unwrap();
+        assert!(file_crypt.password_only_loss(&path).unwrap().is_empty());
+
+        file_crypt.set_recovery(b"ABCD-EFGH-JKMN-PQRS");
+        file_crypt.set_padding(Padding::Padme);
+        let mut cipher = Vec::new();
+        file_crypt
+            .encrypt_stream(&mut Cursor::new(plain), plain.len(), &mut cipher)
+            .unwrap();
+        fs::write(&path, &cipher).unwrap();
+        assert_eq!(
+            file_crypt.password_only_loss(&path).unwrap(),
+            vec!["recovery code slot", "padding"]
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_is_inner_link() {
+        assert!(is_inner_link(Path::new("a/b")));
+        assert!(is_inner_link(Path::new("./a")));
+        assert!(!is_inner_link(Path::new("/etc")));
+        assert!(!is_inner_link(Path::new("../a")));
+        assert!(!is_inner_link(Path::new("a/../../b")));
+    }
+
+    #[test]
+    fn test_signature() {
+        use std::env;
+        use std::process;
+        use untrusted;
+
+        let secret = [1u8; 16];
+        let (pkcs8, public_key) = sign::generate().unwrap();
+        let signer = Ed25519KeyPair::from_pkcs8(untrusted::Input::from(&pkcs8)).unwrap();
+        let (_, other_key) = sign::generate().unwrap();
+        let plain: Vec<u8> = (0..BLOCK_SIZE + 7).map(|i| (i % 251) as u8).collect();
+        let path = env::temp_dir().join(format!("eakio-test-signature-{}", process::id()));
+
+        let mut file_crypt = FileCrypt::new(&secret);
+        file_crypt.set_signer(&signer);
+        let mut cipher = Vec::new();
+        file_crypt
+            .encrypt_stream(&mut Cursor::new(&plain), plain.len(), &mut cipher)
+            .unwrap();
+
+        let trusted = vec![public_key.clone()];
+        let mut out = Vec::new();
+        let mut file_crypt = FileCrypt::new(&secret);
+        file_crypt.set_trusted(&trusted);
+        file_crypt
+            .decrypt_stream(&mut Cursor::new(&cipher), cipher.len(), &mut out)
+            .unwrap();
+        assert_eq!(plain, out);
+
+        // signature still valid after rekey
+        fs::write(&path, &cipher).unwrap();
+        let dest = path.with_extension("dest");
+        assert!(FileCrypt::new(&secret).rewrap(&[2u8; 16], &path, &dest).unwrap());
+        assert_eq!(verify_signature(&dest).unwrap(), public_key);
+        fs::remove_file(&dest).unwrap();
+
+        // bad signature
+        let mut bad = cipher.clone();
+        let last = bad.len() - 1;
+        bad[last] ^= 1;
+        fs::write(&path, &bad).unwrap();
+        assert!(verify_signature(&path).is_err());
+        fs::remove_file(&path).unwrap();
+        assert!(
+            file_crypt
+                .decrypt_stream(&mut Cursor::new(&bad), bad.len(), &mut Vec::new())
+                .is_err()
+        );
+
+        // untrusted signer, or not signed
+        let trusted = vec![other_key];
+        let mut file_crypt = FileCrypt::new(&secret);
+        file_crypt.set_trusted(&trusted);
+        assert!(
+            file_crypt
+                .decrypt_stream(&mut Cursor::new(&cipher), cipher.len(), &mut Vec::new())
+                .is_err()
+        );
+
+        let mut unsigned = Vec::new();
+        FileCrypt::new(&secret)
+            .encrypt_stream(&mut Cursor::new(&plain), plain.len(), &mut unsigned)
+            .unwrap();
+        assert!(
+            file_crypt
+                .decrypt_stream(&mut Cursor::new(&unsigned), unsigned.len(), &mut Vec::new())
+                .is_err()
+        );
+    }
 }
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+
+use super::util::io_error;
+
+pub const IGNORE_FILE: &str = ".eakioignore";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+// one line in ignore file, gitignore like
+#[derive(Debug)]
+struct Rule {
+    pattern: Pattern,
+    negate: bool,
+    dir_only: bool,
+    // pattern contains '/', match path relative to ignore file dir,
+    // otherwise only match file name
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negate, line) = if let Some(rest) = line.strip_prefix('!') {
+            (true, rest)
+        } else if let Some(rest) = line.strip_prefix('\\') {
+            (false, rest)
+        } else {
+            (false, line)
+        };
+
+        let (dir_only, line) = if line.ends_with('/') {
+            (true, line.trim_end_matches('/'))
+        } else {
+            (false, line)
+        };
+
+        let anchored = line.contains('/');
+        let line = line.trim_start_matches('/');
+        if line.is_empty() {
+            return None;
+        }
+
+        match Pattern::new(line) {
+            Ok(pattern) => Some(Rule {
+                pattern,
+                negate,
+                dir_only,
+                anchored,
+            }),
+            Err(e) => {
+                warn!("invalid ignore pattern '{}', {}", line, e);
+                None
+            }
+        }
+    }
+
+    fn matches(&self, rel: &str, name: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let target = if self.anchored { rel } else { name };
+        self.pattern.matches_with(target, &MATCH_OPTIONS)
+    }
+}
+
+fn parse_rules(content: &str) -> Vec<Rule> {
+    content.lines().filter_map(Rule::parse).collect()
+}
+
+/// decide which files should be skipped, by --exclude, --include and
+/// .eakioignore file in every dir
+pub struct Filter {
+    excludes: Vec<Pattern>,
+    includes: Vec<Pattern>,
+    // ignore rules of dir, loaded when first used
+    dirs: HashMap<PathBuf, Vec<Rule>>,
+}
+
+impl Filter {
+    pub fn new(excludes: &[String], includes: &[String]) -> io::Result<Filter> {
+        Ok(Filter {
+            excludes: compile_patterns(excludes)?,
+            includes: compile_patterns(includes)?,
+            dirs: HashMap::new(),
+        })
+    }
+
+    /// is path (under root dir) excluded, parent dirs of path not checked
+    pub fn is_excluded(&mut self, root: &Path, path: &Path, is_dir: bool) -> bool {
+        let rel = match path.strip_prefix(root) {
+            Ok(rel) => rel,
+            Err(_) => return false,
+        };
+        // root itself never excluded
+        if rel.as_os_str().is_empty() {
+            return false;
+        }
+
+        let rel_str = slash_path(rel);
+        let name = path.file_name()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        if matches_any(&self.excludes, &name, &rel_str) {
+            return true;
+        }
+        if !is_dir && !self.includes.is_empty() && !matches_any(&self.includes, &name, &rel_str) {
+            return true;
+        }
+
+        self.is_ignored(root, rel, &name, is_dir)
+    }
+
+    /// like is_excluded, but parent dirs of path also checked
+    pub fn is_path_excluded(&mut self, root: &Path, path: &Path) -> bool {
+        let rel = match path.strip_prefix(root) {
+            Ok(rel) => rel.to_path_buf(),
+            Err(_) => return false,
+        };
+
+        let mut dir = root.to_path_buf();
+        if let Some(parent) = rel.parent() {
+            for comp in parent.components() {
+                dir.push(comp.as_os_str());
+                if self.is_excluded(root, &dir, true) {
+                    return true;
+                }
+            }
+        }
+
+        self.is_excluded(root, path, path.is_dir())
+    }
+
+    // check ignore files from root to the dir contains path, the last
+    // matched rule decide ignore or not, like gitignore
+    fn is_ignored(&mut self, root: &Path, rel: &Path, name: &str, is_dir: bool) -> bool {
+        let mut ignored = false;
+        let mut dir = root.to_path_buf();
+        let mut comps: Vec<Component> = rel.components().collect();
+        comps.pop();
+
+        for i in 0..comps.len() + 1 {
+            if i > 0 {
+                dir.push(comps[i - 1].as_os_str());
+            }
+
+            let sub = slash_path(rel.strip_prefix(dir.strip_prefix(root).unwrap()).unwrap());
+            for rule in self.rules(&dir) {
+                if rule.matches(&sub, name, is_dir) {
+                    ignored = !rule.negate;
+                }
+            }
+        }
+
+        ignored
+    }
+
+    fn rules(&mut self, dir: &Path) -> &[Rule] {
+        self.dirs
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| match load_rules(&dir.join(IGNORE_FILE)) {
+                Ok(rules) => rules,
+                Err(e) => {
+                    warn!("load ignore file in {:?} error, {}", dir, e);
+                    Vec::new()
+                }
+            })
+    }
+}
+
+fn load_rules(path: &Path) -> io::Result<Vec<Rule>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+    Ok(parse_rules(&content))
+}
+
+// pattern match file name or relative path
+fn matches_any(patterns: &[Pattern], name: &str, rel: &str) -> bool {
+    patterns.iter().any(|p| {
+        p.matches_with(name, &MATCH_OPTIONS) || p.matches_with(rel, &MATCH_OPTIONS)
+    })
+}
+
+fn compile_patterns(patterns: &[String]) -> io::Result<Vec<Pattern>> {
+    patterns
+        .iter()
+        .map(|p| {
+            Pattern::new(p).map_err(|e| io_error(&format!("invalid pattern '{}', {}", p, e)))
+        })
+        .collect()
+}
+
+// path use '/' as separator on all platforms, for pattern match
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter(root: &str, ignore: &str) -> Filter {
+        let mut filter = Filter::new(&[], &[]).unwrap();
+        filter
+            .dirs
+            .insert(PathBuf::from(root), parse_rules(ignore));
+        filter
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let rules = parse_rules("# comment\n\n*.tmp\n!keep.tmp\nbuild/\n/doc/*.txt\n\\#name\n");
+        assert_eq!(rules.len(), 5);
+
+        assert!(!rules[0].negate && !rules[0].anchored);
+        assert!(rules[1].negate);
+        assert!(rules[2].dir_only && !rules[2].anchored);
+        assert!(rules[3].anchored);
+        assert!(rules[4].pattern.matches("#name"));
+    }
+
+    #[test]
+    fn test_ignore_rules() {
+        let root = Path::new("/r");
+        let mut f = filter("/r", "*.tmp\n!keep.tmp\nbuild/\n/doc/*.txt\n");
+
+        assert!(f.is_excluded(root, Path::new("/r/a.tmp"), false));
+        assert!(f.is_excluded(root, Path::new("/r/x/y/a.tmp"), false));
+        assert!(!f.is_excluded(root, Path::new("/r/keep.tmp"), false));
+        assert!(f.is_excluded(root, Path::new("/r/x/build"), true));
+        assert!(!f.is_excluded(root, Path::new("/r/x/build"), false));
+        assert!(f.is_excluded(root, Path::new("/r/doc/a.txt"), false));
+        assert!(!f.is_excluded(root, Path::new("/r/x/doc/a.txt"), false));
+        assert!(!f.is_excluded(root, Path::new("/r/a.txt"), false));
+        assert!(!f.is_excluded(root, root, true));
+    }
+
+    #[test]
+    fn test_ignore_nested() {
+        let root = Path::new("/r");
+        let mut f = filter("/r", "*.log\n");
+        f.dirs.insert(PathBuf::from("/r/x"), parse_rules("!a.log\n"));
+
+        assert!(f.is_excluded(root, Path::new("/r/a.log"), false));
+        assert!(!f.is_excluded(root, Path::new("/r/x/a.log"), false));
+        assert!(f.is_excluded(root, Path::new("/r/x/b.log"), false));
+    }
+
+    #[test]
+    fn test_exclude_include() {
+        let root = Path::new("/r");
+        let mut f = Filter::new(&["*.swp".to_owned()], &["*.pdf".to_owned()]).unwrap();
+        f.dirs.insert(PathBuf::from("/r"), Vec::new());
+        f.dirs.insert(PathBuf::from("/r/x"), Vec::new());
+
+        assert!(f.is_excluded(root, Path::new("/r/a.swp"), false));
+        assert!(f.is_excluded(root, Path::new("/r/a.txt"), false));
+        assert!(!f.is_excluded(root, Path::new("/r/a.pdf"), false));
+        assert!(!f.is_excluded(root, Path::new("/r/x"), true));
+        assert!(!f.is_excluded(root, Path::new("/r/x/a.pdf"), false));
+    }
+}
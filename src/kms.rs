@@ -0,0 +1,392 @@
+use std::env;
+use std::io;
+
+use base64;
+use ureq::{self, Agent};
+
+use super::util::io_error;
+
+pub const TOKEN_ENV: &str = "VAULT_TOKEN";
+
+const TOKEN_HEADER: &str = "X-Vault-Token";
+
+/// external key service wraps data keys, its key never leaves it, so
+/// access can be revoked there without touching files
+pub trait KeyProvider: Sync {
+    fn wrap_key(&self, dek: &[u8]) -> io::Result<Vec<u8>>;
+    fn unwrap_key(&self, wrapped: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// provider of kms uri, token from $VAULT_TOKEN
+pub fn from_uri(uri: &str) -> io::Result<Box<dyn KeyProvider>> {
+    let token =
+        env::var(TOKEN_ENV).map_err(|_| io_error(&format!("kms need ${} set", TOKEN_ENV)))?;
+    Ok(Box::new(VaultTransit::new(uri, &token)?))
+}
+
+/// HashiCorp Vault transit engine, uri like vault://<host>[:port]/<mount>/<key>,
+/// vault+http:// for local dev server without tls
+pub struct VaultTransit {
+    agent: Agent,
+    // url of the mount
+    base: String,
+    key: String,
+    token: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TransitData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plaintext: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ciphertext: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TransitResponse {
+    data: TransitData,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransitError {
+    #[serde(default)]
+    errors: Vec<String>,
+}
+
+impl VaultTransit {
+    pub fn new(uri: &str, token: &str) -> io::Result<VaultTransit> {
+        let (scheme, rest) = if let Some(rest) = uri.strip_prefix("vault://") {
+            ("https", rest)
+        } else if let Some(rest) = uri.strip_prefix("vault+http://") {
+            ("http", rest)
+        } else {
+            return Err(io_error(&format!(
+                "kms '{}' not support, need vault://",
+                uri
+            )));
+        };
+
+        // host, mount path (may be nested), key name
+        let parts: Vec<&str> = rest.trim_end_matches('/').split('/').collect();
+        if parts.len() < 3 || parts.iter().any(|p| p.is_empty()) {
+            return Err(io_error(&format!(
+                "kms '{}' not valid, need vault://<host>/<mount>/<key>",
+                uri
+            )));
+        }
+
+        let (key, mount) = parts[1..].split_last().unwrap();
+        Ok(VaultTransit {
+            agent: Agent::new(),
+            base: format!("{}://{}/v1/{}", scheme, parts[0], mount.join("/")),
+            key: key.to_string(),
+            token: token.to_owned(),
+        })
+    }
+
+    fn post(&self, op: &str, body: &TransitData) -> io::Result<TransitData> {
+        let url = format!("{}/{}/{}", self.base, op, self.key);
+
+        let resp = self
+            .agent
+            .post(&url)
+            .set(TOKEN_HEADER, &self.token)
+            .send_json(body);
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(ureq::Error::Status(status, resp)) => {
+                let errors = resp
+                    .into_json::<TransitError>()
+                    .map(|e| e.errors.join(", "))
+                    .unwrap_or_default();
+                return Err(io_error(&format!(
+                    "kms {} {}, {} {}",
+                    op, url, status, errors
+                )));
+            }
+            Err(e) => return Err(io_error(&format!("kms {} {}, {}", op, url, e))),
+        };
+
+        resp.into_json::<TransitResponse>()
+            .map(|r| r.data)
+            .map_err(|e| io_error(&format!("kms {} {}, {}", op, url, e)))
+    }
+}
+
+impl KeyProvider for VaultTransit {
+    fn wrap_key(&self, dek: &[u8]) -> io::Result<Vec<u8>> {
+        let body = TransitData {
+            plaintext: Some(base64::encode(dek)),
+            ..Default::default()
+        };
+
+        self.post("encrypt", &body)?
+            .ciphertext
+            .map(String::into_bytes)
+            .ok_or_else(|| io_error("kms encrypt response has no ciphertext"))
+    }
+
+    fn unwrap_key(&self, wrapped: &[u8]) -> io::Result<Vec<u8>> {
+        let ciphertext = String::from_utf8(wrapped.to_vec())
+            .map_err(|_| io_error("kms wrapped key not valid"))?;
+        let body = TransitData {
+            ciphertext: Some(ciphertext),
+            ..Default::default()
+        };
+
+        let plaintext = self
+            .post("decrypt", &body)?
+            .plaintext
+            .ok_or_else(|| io_error("kms decrypt response has no plaintext"))?;
+        base64::decode(&plaintext).map_err(|e| io_error(&format!("kms decrypt response, {}", e)))
+    }
+}
+
+/// in-process mock of Vault transit encrypt and decrypt api, plain http,
+/// one connection at a time
+#[cfg(test)]
+pub mod mock {
+    use std::io;
+    use std::io::prelude::*;
+    use std::io::BufReader;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use base64;
+    use serde_json;
+
+    use super::{TransitData, TransitResponse};
+    use crypto::{random_key, Crypto, Salt};
+    use util::io_error;
+
+    pub struct MockTransit {
+        addr: SocketAddr,
+        revoked: Arc<AtomicBool>,
+    }
+
+    impl MockTransit {
+        pub fn start(token: &str) -> MockTransit {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let revoked = Arc::new(AtomicBool::new(false));
+
+            let server = Server {
+                key: random_key().unwrap(),
+                token: token.to_owned(),
+                revoked: revoked.clone(),
+            };
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let _ = server.handle(stream);
+                }
+            });
+
+            MockTransit { addr, revoked }
+        }
+
+        pub fn uri(&self, key: &str) -> String {
+            format!("vault+http://{}/transit/{}", self.addr, key)
+        }
+
+        /// refuse all decrypt requests since now
+        pub fn revoke(&self) {
+            self.revoked.store(true, Ordering::SeqCst);
+        }
+    }
+
+    struct Server {
+        key: Vec<u8>,
+        token: String,
+        revoked: Arc<AtomicBool>,
+    }
+
+    impl Server {
+        fn handle(&self, stream: TcpStream) -> io::Result<()> {
+            let mut reader = BufReader::new(stream.try_clone()?);
+
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let path = line.split_whitespace().nth(1).unwrap_or("").to_owned();
+
+            let mut token = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(pos) = line.find(':') {
+                    let (name, value) = (line[..pos].to_lowercase(), line[pos + 1..].trim());
+                    if name == "x-vault-token" {
+                        token = value.to_owned();
+                    } else if name == "content-length" {
+                        length = value.parse().unwrap_or(0);
+                    }
+                }
+            }
+
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body)?;
+
+            let (status, json) = if token != self.token {
+                (
+                    "403 Forbidden",
+                    r#"{"errors":["permission denied"]}"#.to_owned(),
+                )
+            } else {
+                match self.route(&path, &body) {
+                    Ok(data) => (
+                        "200 OK",
+                        serde_json::to_string(&TransitResponse { data }).unwrap(),
+                    ),
+                    Err(e) => ("400 Bad Request", format!(r#"{{"errors":["{}"]}}"#, e)),
+                }
+            };
+
+            let mut stream = stream;
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                json.len(),
+                json
+            )
+        }
+
+        // path like /v1/transit/<op>/<key>
+        fn route(&self, path: &str, body: &[u8]) -> io::Result<TransitData> {
+            let parts: Vec<&str> = path.split('/').collect();
+            if parts.len() != 5 || parts[1] != "v1" || parts[2] != "transit" {
+                return Err(io_error("unknown path"));
+            }
+
+            let req: TransitData =
+                serde_json::from_slice(body).map_err(|_| io_error("invalid body"))?;
+            let mut secret = self.key.clone();
+            secret.extend_from_slice(parts[4].as_bytes());
+
+            match parts[3] {
+                "encrypt" => {
+                    let plain = req
+                        .plaintext
+                        .and_then(|p| base64::decode(&p).ok())
+                        .ok_or_else(|| io_error("invalid plaintext"))?;
+                    let salt = Salt::new()?;
+                    let mut buf = plain.clone();
+                    buf.resize(plain.len() + Crypto::tag_len(), 0);
+                    let len = Crypto::new(&secret, &salt)?.encrypt(&mut buf, plain.len())?;
+
+                    let mut data = salt.get_bytes().to_vec();
+                    data.extend_from_slice(&buf[..len]);
+                    Ok(TransitData {
+                        ciphertext: Some(format!("vault:v1:{}", base64::encode(&data))),
+                        ..Default::default()
+                    })
+                }
+                "decrypt" => {
+                    if self.revoked.load(Ordering::SeqCst) {
+                        return Err(io_error("key revoked"));
+                    }
+
+                    let data = req
+                        .ciphertext
+                        .and_then(|c| {
+                            c.strip_prefix("vault:v1:")
+                                .and_then(|c| base64::decode(c).ok())
+                        })
+                        .ok_or_else(|| io_error("invalid ciphertext"))?;
+                    if data.len() < Salt::len() {
+                        return Err(io_error("invalid ciphertext"));
+                    }
+
+                    let salt = Salt::from_bytes(&data[..Salt::len()])?;
+                    let mut buf = data[Salt::len()..].to_vec();
+                    let len = Crypto::new(&secret, &salt)?.decrypt(&mut buf)?;
+                    Ok(TransitData {
+                        plaintext: Some(base64::encode(&buf[..len])),
+                        ..Default::default()
+                    })
+                }
+                _ => Err(io_error("unknown operation")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::mock::MockTransit;
+    use super::*;
+
+    #[test]
+    fn test_parse_uri() {
+        let vault = VaultTransit::new("vault://vault.local:8200/transit/backup", "t").unwrap();
+        assert_eq!(vault.base, "https://vault.local:8200/v1/transit");
+        assert_eq!(vault.key, "backup");
+
+        let vault = VaultTransit::new("vault+http://127.0.0.1/team/transit/k/", "t").unwrap();
+        assert_eq!(vault.base, "http://127.0.0.1/v1/team/transit");
+        assert_eq!(vault.key, "k");
+
+        assert!(VaultTransit::new("http://vault.local/transit/k", "t").is_err());
+        assert!(VaultTransit::new("vault://vault.local/k", "t").is_err());
+        assert!(VaultTransit::new("vault://vault.local//k", "t").is_err());
+    }
+
+    #[test]
+    fn test_vault_transit() {
+        let mock = MockTransit::start("token");
+        let vault = VaultTransit::new(&mock.uri("backup"), "token").unwrap();
+
+        let dek = [7u8; 32];
+        let wrapped = vault.wrap_key(&dek).unwrap();
+        assert!(wrapped.starts_with(b"vault:v1:"));
+        assert_eq!(vault.unwrap_key(&wrapped).unwrap(), dek);
+
+        // other key or token can not unwrap
+        let other = VaultTransit::new(&mock.uri("other"), "token").unwrap();
+        assert!(other.unwrap_key(&wrapped).is_err());
+        let bad = VaultTransit::new(&mock.uri("backup"), "bad").unwrap();
+        assert!(bad.unwrap_key(&wrapped).is_err());
+
+        mock.revoke();
+        assert!(vault.unwrap_key(&wrapped).is_err());
+    }
+
+    #[test]
+    fn test_file_crypt_kms() {
+        use file::FileCrypt;
+        use std::io::Cursor;
+
+        let mock = MockTransit::start("token");
+        let vault = VaultTransit::new(&mock.uri("backup"), "token").unwrap();
+        let plain = b"hello kelsi";
+
+        let mut file_crypt = FileCrypt::new(&[]);
+        file_crypt.set_kms(&vault);
+        let mut cipher = Vec::new();
+        file_crypt
+            .encrypt_stream(&mut Cursor::new(plain), plain.len(), &mut cipher)
+            .unwrap();
+
+        let mut out = Vec::new();
+        file_crypt
+            .decrypt_stream(&mut Cursor::new(&cipher), cipher.len(), &mut out)
+            .unwrap();
+        assert_eq!(&out[..], &plain[..]);
+
+        // password can not open it, kms can not after revoked
+        let mut out = Vec::new();
+        assert!(FileCrypt::new(&[])
+            .decrypt_stream(&mut Cursor::new(&cipher), cipher.len(), &mut out)
+            .is_err());
+        mock.revoke();
+        assert!(file_crypt
+            .decrypt_stream(&mut Cursor::new(&cipher), cipher.len(), &mut out)
+            .is_err());
+    }
+}
@@ -1,24 +1,47 @@
 extern crate ansi_term;
+extern crate base64;
 extern crate byteorder;
 extern crate crossbeam;
 extern crate docopt;
 extern crate env_logger;
 extern crate glob;
+extern crate libc;
 #[macro_use]
 extern crate log;
+extern crate notify;
 extern crate num_cpus;
 extern crate ring;
 extern crate rpassword;
 extern crate scoped_threadpool;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate time;
+extern crate toml;
+extern crate untrusted;
+extern crate ureq;
 extern crate walkdir;
 
+#[cfg(unix)]
+mod agent;
+mod cmd;
+mod config;
 mod crypto;
+mod dotenv;
 mod file;
+mod ignore;
+mod kms;
+mod manifest;
+mod padding;
+mod recovery;
+mod scan;
+mod shamir;
+mod sign;
+mod sync;
 mod task;
 mod util;
+mod watch;
 mod cli;
 
 pub use cli::command;
@@ -0,0 +1,397 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use base64;
+use ring::signature::Ed25519KeyPair;
+use ring::{digest, hmac};
+use walkdir::WalkDir;
+
+use super::file::{header_hash, probe_version};
+use super::sign;
+use super::task::tmp_path;
+use super::util::{absolute_link_path, from_hex, io_error, sync_dir, sync_file, to_hex};
+
+const HEADER: &str = "eakio-manifest 1";
+const INFO_MAC: &[u8] = b"kelsi manifest";
+
+/// how manifest is authenticated
+pub enum Seal<'a> {
+    // HMAC keyed by password
+    Mac(&'a [u8]),
+    Sign(&'a Ed25519KeyPair),
+}
+
+// size and header hash of an encrypted file
+#[derive(Debug, PartialEq)]
+struct Entry {
+    size: u64,
+    hash: Vec<u8>,
+}
+
+/// list of encrypted files in a tree, paths relative to manifest dir
+///
+/// ```text
+/// eakio-manifest 1
+/// <header hash> <size> <path>
+/// ...
+/// mac <hmac>  or  sig <public key> <signature>
+/// ```
+#[derive(Debug, Default)]
+pub struct Manifest {
+    entries: BTreeMap<String, Entry>,
+    // last line as read, None for new one
+    seal: Option<String>,
+}
+
+/// files differ from manifest
+#[derive(Debug, Default)]
+pub struct Report {
+    pub missing: Vec<String>,
+    // size or header hash changed, replaced or rolled back
+    pub changed: Vec<String>,
+    // encrypted files in the dir not listed
+    pub extra: Vec<String>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty() && self.extra.is_empty()
+    }
+}
+
+impl Manifest {
+    /// files must be in the dir of manifest, check it before they are written
+    pub fn check_files(path: &Path, files: &[PathBuf]) -> io::Result<()> {
+        let root = manifest_dir(path)?;
+        for path in files {
+            relative_name(&root, &absolute_link_path(path)?)?;
+        }
+        Ok(())
+    }
+
+    /// manifest of encrypted files, they must be in the dir of manifest
+    pub fn build(path: &Path, files: &[PathBuf]) -> io::Result<Manifest> {
+        let root = manifest_dir(path)?;
+        let mut manifest = Manifest::default();
+        for path in files {
+            let name = relative_name(&root, &absolute_link_path(path)?)?;
+            manifest.entries.insert(name, file_entry(path)?);
+        }
+        Ok(manifest)
+    }
+
+    pub fn write(&self, path: &Path, seal: &Seal) -> io::Result<()> {
+        let body = self.body();
+        let seal = match *seal {
+            Seal::Mac(secret) => format!(
+                "mac {}",
+                to_hex(hmac::sign(&mac_key(secret), body.as_bytes()).as_ref())
+            ),
+            Seal::Sign(signer) => format!(
+                "sig {} {}",
+                base64::encode(signer.public_key_bytes()),
+                base64::encode(signer.sign(body.as_bytes()).as_ref())
+            ),
+        };
+
+        // write aside then rename, never leave a broken manifest
+        let tmp = tmp_path(path);
+        let result = fs::write(&tmp, format!("{}{}\n", body, seal))
+            .and_then(|_| sync_file(&tmp))
+            .and_then(|_| fs::rename(&tmp, path))
+            .and_then(|_| sync_dir(path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result
+    }
+
+    pub fn load(path: &Path) -> io::Result<Manifest> {
+        let content = fs::read_to_string(path)?;
+        let mut lines = content.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(io_error("manifest header not valid"));
+        }
+
+        let mut manifest = Manifest::default();
+        for line in lines {
+            if line.starts_with("mac ") || line.starts_with("sig ") {
+                manifest.seal = Some(line.to_owned());
+                break;
+            }
+
+            let parts: Vec<&str> = line.splitn(3, ' ').collect();
+            if parts.len() != 3 {
+                return Err(io_error(&format!("manifest line '{}' not valid", line)));
+            }
+            let entry = Entry {
+                size: parts[1]
+                    .parse()
+                    .map_err(|_| io_error(&format!("manifest line '{}' not valid", line)))?,
+                hash: from_hex(parts[0])?,
+            };
+            manifest.entries.insert(parts[2].to_owned(), entry);
+        }
+
+        if manifest.seal.is_none() {
+            return Err(io_error("manifest not sealed"));
+        }
+        Ok(manifest)
+    }
+
+    /// sealed by HMAC, password needed to check it
+    pub fn need_secret(&self) -> bool {
+        match self.seal {
+            Some(ref seal) => seal.starts_with("mac "),
+            None => false,
+        }
+    }
+
+    /// check seal of loaded manifest, with trusted signers, it must be
+    /// signed by one of them. signed one needs trusted signers, the key
+    /// in it proves nothing. returns the signer if signed
+    pub fn authenticate(&self, secret: &[u8], trusted: &[Vec<u8>]) -> io::Result<Option<Vec<u8>>> {
+        let seal = self
+            .seal
+            .as_ref()
+            .ok_or_else(|| io_error("manifest not sealed"))?;
+        let parts: Vec<&str> = seal.split(' ').collect();
+        let body = self.body();
+
+        match (parts[0], parts.len()) {
+            ("mac", 2) => {
+                if !trusted.is_empty() {
+                    return Err(io_error("manifest not signed"));
+                }
+                let tag = from_hex(parts[1])?;
+                hmac::verify_with_own_key(&mac_key(secret), body.as_bytes(), &tag)
+                    .map_err(|_| io_error("manifest mac not match, wrong password?"))?;
+                Ok(None)
+            }
+            ("sig", 3) => {
+                if trusted.is_empty() {
+                    return Err(io_error("manifest signed, need trusted signers to check it"));
+                }
+                // not read_public_key, never take it as a path
+                let public_key = base64::decode(parts[1])
+                    .map_err(|_| io_error("manifest signer not valid"))?;
+                let sig = base64::decode(parts[2])
+                    .map_err(|_| io_error("manifest signature not valid"))?;
+                if !sign::verify(&public_key, body.as_bytes(), &sig) {
+                    return Err(io_error("manifest signature not valid"));
+                }
+                if !trusted.contains(&public_key) {
+                    return Err(io_error(&format!(
+                        "manifest signer '{}' not trusted",
+                        parts[1]
+                    )));
+                }
+                Ok(Some(public_key))
+            }
+            _ => Err(io_error("manifest seal not valid")),
+        }
+    }
+
+    /// compare files in the dir of manifest with it. encrypted files not
+    /// listed are extra, plain ones, manifest itself and tmp files skipped
+    pub fn check(&self, path: &Path) -> io::Result<Report> {
+        let root = manifest_dir(path)?;
+        let own = absolute_link_path(path)?;
+        let own_tmp = tmp_path(&own);
+        let mut report = Report::default();
+
+        for (name, expect) in &self.entries {
+            let file = root.join(name);
+            if fs::symlink_metadata(&file).is_err() {
+                report.missing.push(name.clone());
+            } else if file_entry(&file).ok().as_ref() != Some(expect) {
+                report.changed.push(name.clone());
+            }
+        }
+
+        for entry in WalkDir::new(&root) {
+            let entry = entry?;
+            if !entry.file_type().is_file() || entry.path() == own || entry.path() == own_tmp {
+                continue;
+            }
+            let name = relative_name(&root, entry.path())?;
+            if self.entries.contains_key(&name) {
+                continue;
+            }
+            if let Ok(Some(_)) = probe_version(entry.path()) {
+                report.extra.push(name);
+            }
+        }
+        report.extra.sort();
+
+        Ok(report)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // all before the seal line, what is authenticated
+    fn body(&self) -> String {
+        let mut body = format!("{}\n", HEADER);
+        for (name, entry) in &self.entries {
+            body.push_str(&format!(
+                "{} {} {}\n",
+                to_hex(&entry.hash),
+                entry.size,
+                name
+            ));
+        }
+        body
+    }
+}
+
+fn manifest_dir(path: &Path) -> io::Result<PathBuf> {
+    let abs = absolute_link_path(path)?;
+    Ok(abs.parent().unwrap().to_path_buf())
+}
+
+fn file_entry(path: &Path) -> io::Result<Entry> {
+    Ok(Entry {
+        size: fs::metadata(path)?.len(),
+        hash: header_hash(path).map_err(|e| io_error(&format!("{:?} {}", path, e)))?,
+    })
+}
+
+// path relative to root, joined by '/', same on all platforms
+fn relative_name(root: &Path, path: &Path) -> io::Result<String> {
+    let rel = path
+        .strip_prefix(root)
+        .map_err(|_| io_error(&format!("{:?} not in manifest dir {:?}", path, root)))?;
+
+    let mut parts = Vec::new();
+    for comp in rel.components() {
+        match comp {
+            Component::Normal(part) => match part.to_str() {
+                Some(part) if !part.contains('\n') => parts.push(part),
+                _ => {
+                    return Err(io_error(&format!(
+                        "{:?} name not supported in manifest",
+                        path
+                    )))
+                }
+            },
+            Component::CurDir => {}
+            _ => {
+                return Err(io_error(&format!(
+                    "{:?} not in manifest dir {:?}",
+                    path, root
+                )))
+            }
+        }
+    }
+
+    Ok(parts.join("/"))
+}
+
+// not the password itself, keep it away from other keys
+fn mac_key(secret: &[u8]) -> hmac::SigningKey {
+    let master = hmac::SigningKey::new(&digest::SHA256, secret);
+    let key = hmac::sign(&master, INFO_MAC);
+    hmac::SigningKey::new(&digest::SHA256, key.as_ref())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::io::Cursor;
+    use std::process;
+    use std::slice;
+
+    use file::FileCrypt;
+
+    fn encrypt_to(path: &Path, data: &[u8]) {
+        let mut cipher = Vec::new();
+        FileCrypt::new(b"secret")
+            .encrypt_stream(&mut Cursor::new(data), data.len(), &mut cipher)
+            .unwrap();
+        fs::write(path, &cipher).unwrap();
+    }
+
+    #[test]
+    fn test_manifest() {
+        let root = env::temp_dir().join(format!("eakio-test-manifest-{}", process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        let files = vec![root.join("a.eak"), root.join("sub").join("b c.eak")];
+        for path in &files {
+            encrypt_to(path, b"hello kelsi");
+        }
+        let manifest_path = root.join("manifest");
+
+        let manifest = Manifest::build(&manifest_path, &files).unwrap();
+        manifest
+            .write(&manifest_path, &Seal::Mac(b"secret"))
+            .unwrap();
+
+        let loaded = Manifest::load(&manifest_path).unwrap();
+        assert!(loaded.need_secret());
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.authenticate(b"secret", &[]).unwrap(), None);
+        assert!(loaded.authenticate(b"wrong", &[]).is_err());
+        assert!(loaded.check(&manifest_path).unwrap().is_ok());
+
+        // plain files not listed are fine
+        fs::write(root.join("notes.txt"), b"plain").unwrap();
+        assert!(loaded.check(&manifest_path).unwrap().is_ok());
+
+        // swapped, extra and missing files
+        encrypt_to(&files[0], b"hello kelsi");
+        fs::rename(&files[1], root.join("sub").join("d.eak")).unwrap();
+        let report = loaded.check(&manifest_path).unwrap();
+        assert_eq!(report.changed, vec!["a.eak"]);
+        assert_eq!(report.missing, vec!["sub/b c.eak"]);
+        assert_eq!(report.extra, vec!["sub/d.eak"]);
+
+        assert!(Manifest::check_files(&manifest_path, &files).is_ok());
+        assert!(Manifest::check_files(&manifest_path, &[env::temp_dir().join("x.eak")]).is_err());
+
+        // entry edited
+        let content = fs::read_to_string(&manifest_path).unwrap();
+        fs::write(&manifest_path, content.replace("a.eak", "x.eak")).unwrap();
+        assert!(Manifest::load(&manifest_path)
+            .unwrap()
+            .authenticate(b"secret", &[])
+            .is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_signed_manifest() {
+        use untrusted;
+
+        let root = env::temp_dir().join(format!("eakio-test-signed-manifest-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let files = vec![root.join("a.eak")];
+        encrypt_to(&files[0], b"hello kelsi");
+        let manifest_path = root.join("manifest");
+
+        let (pkcs8, public_key) = sign::generate().unwrap();
+        let signer = Ed25519KeyPair::from_pkcs8(untrusted::Input::from(&pkcs8)).unwrap();
+        let manifest = Manifest::build(&manifest_path, &files).unwrap();
+        manifest
+            .write(&manifest_path, &Seal::Sign(&signer))
+            .unwrap();
+
+        let loaded = Manifest::load(&manifest_path).unwrap();
+        assert!(!loaded.need_secret());
+        assert_eq!(
+            loaded.authenticate(&[], slice::from_ref(&public_key)).unwrap(),
+            Some(public_key)
+        );
+
+        let (_, other) = sign::generate().unwrap();
+        assert!(loaded.authenticate(&[], &[other]).is_err());
+        assert!(loaded.authenticate(&[], &[]).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
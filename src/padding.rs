@@ -0,0 +1,116 @@
+use std::io;
+
+use super::util::io_error;
+
+/// how encrypted data is padded, so size tells less about the content
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Padding {
+    #[default]
+    None,
+    // Padmé, at most 12% overhead, size keeps only O(log log) bits
+    Padme,
+    // round up to multiple of the size
+    Block(usize),
+}
+
+impl Padding {
+    /// parse `padme`, `block:<size>` or `none`, size can end with K, M, G
+    pub fn parse(text: &str) -> io::Result<Padding> {
+        let text = text.trim().to_lowercase();
+        match text.as_str() {
+            "none" => return Ok(Padding::None),
+            "padme" => return Ok(Padding::Padme),
+            _ => {}
+        }
+
+        if !text.starts_with("block:") {
+            return Err(io_error(&format!(
+                "padding '{}' not support, need padme, block:<size> or none",
+                text
+            )));
+        }
+        let size = &text["block:".len()..];
+        let (num, unit) = match size.chars().last() {
+            Some('k') => (&size[..size.len() - 1], 1 << 10),
+            Some('m') => (&size[..size.len() - 1], 1 << 20),
+            Some('g') => (&size[..size.len() - 1], 1 << 30),
+            _ => (size, 1),
+        };
+
+        match num.parse::<usize>().ok().and_then(|n| n.checked_mul(unit)) {
+            Some(n) if n > 0 => Ok(Padding::Block(n)),
+            _ => Err(io_error(&format!(
+                "padding block size '{}' not valid",
+                size
+            ))),
+        }
+    }
+
+    /// size padded to, never smaller than len
+    pub fn size(&self, len: usize) -> io::Result<usize> {
+        let size = match *self {
+            Padding::None => Some(len),
+            Padding::Block(block) => len.checked_add(block - 1).map(|n| n / block * block),
+            Padding::Padme => padme(len),
+        };
+
+        size.ok_or_else(|| io_error(&format!("size {} too large to pad", len)))
+    }
+}
+
+// keep the top log2(log2(len)) + 1 bits of len, round up the others,
+// None if overflow
+fn padme(len: usize) -> Option<usize> {
+    if len < 2 {
+        return Some(len);
+    }
+
+    let bits = 0usize.leading_zeros() as usize;
+    let exp = bits - 1 - len.leading_zeros() as usize;
+    let exp_bits = bits - exp.leading_zeros() as usize;
+    let mask = (1usize << (exp - exp_bits)) - 1;
+
+    len.checked_add(mask).map(|n| n & !mask)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Padding::parse("none").unwrap(), Padding::None);
+        assert_eq!(Padding::parse("PadMe").unwrap(), Padding::Padme);
+        assert_eq!(Padding::parse("block:512").unwrap(), Padding::Block(512));
+        assert_eq!(Padding::parse("block:4k").unwrap(), Padding::Block(4096));
+        assert_eq!(Padding::parse("block:1M").unwrap(), Padding::Block(1 << 20));
+
+        assert!(Padding::parse("block:0").is_err());
+        assert!(Padding::parse(&format!("block:{}g", usize::MAX)).is_err());
+        assert!(Padding::parse("block:").is_err());
+        assert!(Padding::parse("block").is_err());
+        assert!(Padding::parse("zero").is_err());
+    }
+
+    #[test]
+    fn test_size() {
+        assert_eq!(Padding::None.size(1000).unwrap(), 1000);
+        assert_eq!(Padding::Block(512).size(0).unwrap(), 0);
+        assert_eq!(Padding::Block(512).size(1).unwrap(), 512);
+        assert_eq!(Padding::Block(512).size(512).unwrap(), 512);
+        assert_eq!(Padding::Block(512).size(513).unwrap(), 1024);
+        assert!(Padding::Block(512).size(usize::MAX - 1).is_err());
+        assert!(Padding::Padme.size(usize::MAX - 1).is_err());
+
+        assert_eq!(padme(0), Some(0));
+        assert_eq!(padme(1), Some(1));
+        assert_eq!(padme(9), Some(10));
+        assert_eq!(padme(1000), Some(1024));
+        assert_eq!(padme(1025), Some(1088));
+        for len in (1..100_000).step_by(97) {
+            let padded = padme(len).unwrap();
+            assert!(padded >= len);
+            assert!(padded - len <= len / 8);
+        }
+    }
+}
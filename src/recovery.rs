@@ -0,0 +1,81 @@
+use std::io;
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use super::util::io_error;
+
+// crockford base32, no I, L, O, U to misread
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+// 160 bits, 32 chars
+const CODE_BYTES: usize = 20;
+const GROUP: usize = 4;
+
+/// new random recovery code, grouped like `ABCD-EFGH-...` for printing
+pub fn generate() -> io::Result<String> {
+    let mut bytes = [0u8; CODE_BYTES];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| io_error("generate recovery code error"))?;
+
+    let chars = encode(&bytes);
+    let groups: Vec<String> = chars
+        .chunks(GROUP)
+        .map(|g| String::from_utf8_lossy(g).into_owned())
+        .collect();
+    Ok(groups.join("-"))
+}
+
+/// the key of recovery code as typed, separators and case ignored,
+/// misread chars mapped back
+pub fn normalize(input: &[u8]) -> Vec<u8> {
+    input
+        .iter()
+        .filter(|&&c| c != b'-' && !(c as char).is_whitespace())
+        .map(|&c| match c.to_ascii_uppercase() {
+            b'O' => b'0',
+            b'I' | b'L' => b'1',
+            c => c,
+        })
+        .collect()
+}
+
+fn encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity((data.len() * 8).div_ceil(5));
+    let (mut acc, mut bits) = (0u32, 0);
+    for &b in data {
+        acc = acc << 8 | u32::from(b);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[(acc >> bits) as usize & 0x1f]);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[(acc << (5 - bits)) as usize & 0x1f]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let code = generate().unwrap();
+        assert_eq!(code.len(), 32 + 7);
+        assert_eq!(code.split('-').count(), 8);
+        assert!(code.bytes().all(|c| c == b'-' || ALPHABET.contains(&c)));
+        assert_ne!(code, generate().unwrap());
+
+        assert_eq!(encode(&[0xff, 0x00]), b"ZW00");
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(b"AB0C-1DEF"), b"AB0C1DEF");
+        assert_eq!(normalize(b" ab0c-1def\n"), b"AB0C1DEF");
+        assert_eq!(normalize(b"abOc lDef"), b"AB0C1DEF");
+        assert_eq!(normalize(b"ABOC IDEF"), b"AB0C1DEF");
+    }
+}
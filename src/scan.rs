@@ -0,0 +1,374 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use glob;
+use walkdir::{DirEntry, WalkDir};
+
+use super::file::{is_latest_version, is_legacy_version, is_supported_version, probe_version,
+                  DIR_RECORD, EXTENSION};
+use super::ignore::Filter;
+use super::task::{Kind, Mode, Task};
+use super::util::{absolute_path, expand_tilde_path, io_error};
+
+#[derive(Debug)]
+pub struct PathGroup {
+    pub path: PathBuf,
+    pub is_file: bool,
+    pub subs: Vec<(PathBuf, Kind)>,
+}
+
+// how to handle symlink in src dir
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Symlinks {
+    Skip,
+    Follow,
+    Store,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ListOptions {
+    pub hidden: bool,
+    pub symlinks: Symlinks,
+    // list dirs as Kind::Dir too
+    pub keep_dirs: bool,
+}
+
+impl Default for ListOptions {
+    fn default() -> ListOptions {
+        ListOptions {
+            hidden: false,
+            symlinks: Symlinks::Skip,
+            keep_dirs: false,
+        }
+    }
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry
+        .file_name()
+        .to_str()
+        .map(|s| s.starts_with('.'))
+        .unwrap_or(false)
+}
+
+fn is_dir_record(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name == DIR_RECORD)
+        .unwrap_or(false)
+}
+
+pub fn list_src_files(
+    srcs: &[String],
+    opts: &ListOptions,
+    filter: &mut Filter,
+) -> io::Result<Vec<PathGroup>> {
+    let symlinks = opts.symlinks;
+    let mut globs = Vec::<PathBuf>::new();
+    for src in srcs.iter() {
+        let expand_src = expand_tilde_path(src);
+        let paths = glob::glob(&expand_src).map_err(|e| io_error(&format!("{}", e)))?;
+        for entry in paths {
+            let path = entry.map_err(|e| io_error(&format!("{}", e)))?;
+            globs.push(path);
+        }
+    }
+    let mut path_groups = Vec::<PathGroup>::new();
+
+    for path in globs {
+        let mut subs = Vec::<(PathBuf, Kind)>::new();
+        let is_link = fs::symlink_metadata(&path)?.file_type().is_symlink();
+
+        if path.is_file() || (is_link && symlinks == Symlinks::Store) {
+            let parent = path.parent().unwrap_or_else(|| Path::new(""));
+            if filter.is_excluded(parent, &path, false) {
+                continue;
+            }
+
+            // symlink given in command line is followed, unless store it
+            let kind = if is_link && symlinks == Symlinks::Store {
+                Kind::Symlink
+            } else {
+                Kind::File
+            };
+            subs.push((path.clone(), kind));
+            path_groups.push(PathGroup {
+                path,
+                is_file: true,
+                subs,
+            });
+        } else if path.is_dir() {
+            for entry in WalkDir::new(&path)
+                .follow_links(symlinks == Symlinks::Follow)
+                .into_iter()
+                .filter_entry(|e| {
+                    (opts.hidden || !is_hidden(e) || is_dir_record(e.path()))
+                        && !filter.is_excluded(&path, e.path(), e.file_type().is_dir())
+                }) {
+                let de = match entry {
+                    Ok(de) => de,
+                    Err(e) => {
+                        // broken symlink or loop when follow symlinks
+                        if symlinks == Symlinks::Follow && e.path().is_some() {
+                            warn!("Skip {}", e);
+                            continue;
+                        }
+                        return Err(io_error(&format!("{}", e)));
+                    }
+                };
+
+                let file_type = de.file_type();
+                if file_type.is_file() && is_dir_record(de.path()) {
+                    subs.push((de.path().to_path_buf(), Kind::Dir));
+                } else if file_type.is_file() {
+                    subs.push((de.path().to_path_buf(), Kind::File));
+                } else if file_type.is_symlink() {
+                    if symlinks == Symlinks::Store {
+                        subs.push((de.path().to_path_buf(), Kind::Symlink));
+                    } else {
+                        warn!("Skip symlink {:?}", de.path());
+                    }
+                } else if file_type.is_dir() {
+                    if opts.keep_dirs {
+                        subs.push((de.path().to_path_buf(), Kind::Dir));
+                    }
+                } else {
+                    warn!("Skip special file {:?}", de.path());
+                }
+            }
+
+            path_groups.push(PathGroup {
+                path,
+                is_file: false,
+                subs,
+            });
+        } else {
+            warn!("Skip special file {:?}", path);
+        }
+    }
+
+    Ok(path_groups)
+}
+
+pub fn build_tasks(srcs: &[PathGroup], dest: &PathBuf, dest_is_dir: bool) -> Vec<Task> {
+    let mut tasks = Vec::<Task>::new();
+
+    // 这里目标文件的路径由以下方式决定
+    // - src 是文件
+    //   1. dest 是文件, 则 dest
+    //   2. dest 是目录, 则 dest/filename(src)
+    // - src 是目录, 将 src 到 dest/src
+    // - 目录记录, 加密时 dir 到 dest/src/dir/DIR_RECORD, 解密时相反
+    for pg in srcs.iter() {
+        for (path, kind) in &pg.subs {
+            let mut task_dest = PathBuf::from(&dest);
+
+            if pg.is_file {
+                if dest_is_dir {
+                    let filename = path.file_name().unwrap();
+                    task_dest.push(filename);
+                }
+            } else {
+                // remove prefix
+                let filename = path.strip_prefix(&pg.path).unwrap();
+                if let Some(dirname) = pg.path.file_name() {
+                    task_dest.push(dirname);
+                }
+
+                if *kind != Kind::Dir {
+                    task_dest.push(filename);
+                } else if is_dir_record(path) {
+                    task_dest.push(filename.parent().unwrap());
+                } else {
+                    task_dest.push(filename);
+                    task_dest.push(DIR_RECORD);
+                }
+            }
+
+            tasks.push(Task {
+                src: path.clone(),
+                dest: task_dest,
+                kind: kind.clone(),
+            })
+        }
+    }
+
+    tasks
+}
+
+// tasks dest is src, only duplicated src need be removed
+pub fn list_in_place_tasks(
+    srcs: &[String],
+    hidden: bool,
+    filter: &mut Filter,
+    with_records: bool,
+) -> io::Result<Vec<Task>> {
+    let list_opts = ListOptions {
+        hidden,
+        ..Default::default()
+    };
+    let files = list_src_files(srcs, &list_opts, filter)?;
+
+    let mut seen = HashSet::new();
+    let mut tasks = Vec::<Task>::new();
+    for pg in &files {
+        for (path, kind) in &pg.subs {
+            let wanted = *kind == Kind::File || (with_records && *kind == Kind::Dir);
+            if !wanted || !seen.insert(absolute_path(path)?) {
+                continue;
+            }
+
+            tasks.push(Task {
+                src: path.clone(),
+                dest: path.clone(),
+                kind: Kind::File,
+            });
+        }
+    }
+
+    Ok(tasks)
+}
+
+
+pub fn src_dirs(srcs: &[PathGroup]) -> Vec<PathBuf> {
+    srcs.iter()
+        .filter(|pg| !pg.is_file)
+        .map(|pg| pg.path.clone())
+        .collect()
+}
+
+// encrypt append extension to dest, decrypt remove it
+pub fn apply_extension(tasks: &mut [Task], mode: Mode) {
+    for task in tasks.iter_mut() {
+        if task.kind == Kind::Dir || task.kind == Kind::Copy {
+            continue;
+        }
+
+        match mode {
+            Mode::Encrypt => {
+                let mut name = task.dest.file_name().unwrap().to_os_string();
+                name.push(".");
+                name.push(EXTENSION);
+                task.dest.set_file_name(name);
+            }
+            Mode::Decrypt => if task.dest.extension() == Some(OsStr::new(EXTENSION)) {
+                task.dest.set_extension("");
+            } else {
+                warn!("{:?} has no '.{}' extension", task.src, EXTENSION);
+            },
+            // keep name, file replaced in place
+            Mode::Rekey | Mode::Upgrade => {}
+        }
+    }
+}
+
+// check decrypt src header before run, so not encrypted files will not
+// fail later with a broken dest
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Prescan {
+    // copy not encrypted files unchanged
+    pub copy_plain: bool,
+    // reject VERSION_1 files, no authenticated size
+    pub refuse_v1: bool,
+    // only keep files not in latest version
+    pub only_old: bool,
+}
+
+pub fn prescan_encrypted(tasks: Vec<Task>, mode: Mode, scan: Prescan) -> Vec<Task> {
+    let mut checked = Vec::with_capacity(tasks.len());
+
+    for mut task in tasks {
+        match probe_version(&task.src) {
+            Ok(Some(version)) => if !is_supported_version(version) {
+                error!("{}: {} (version '{}' not support)", mode, task, version);
+            } else if scan.refuse_v1 && is_legacy_version(version) {
+                error!("{}: {} (legacy version '{}' refused)", mode, task, version);
+            } else if scan.only_old && is_latest_version(version) {
+                debug!("{}: {} (skip latest version)", mode, task);
+            } else {
+                checked.push(task);
+            },
+            Ok(None) => if scan.copy_plain {
+                task.kind = Kind::Copy;
+                checked.push(task);
+            } else {
+                warn!("{}: {} (skip not encrypted)", mode, task);
+            },
+            Err(e) => error!("{}: {} ({})", mode, task, e),
+        }
+    }
+
+    checked
+}
+
+// the same file (hard links) only need encrypt or decrypt once, others
+// become hard link of the first one in dest
+#[cfg(unix)]
+pub fn detect_hard_links(tasks: &mut [Task]) {
+    use std::collections::HashMap;
+    use std::os::unix::fs::MetadataExt;
+
+    let mut seen = HashMap::<(u64, u64), PathBuf>::new();
+    for task in tasks.iter_mut() {
+        if task.kind != Kind::File {
+            continue;
+        }
+
+        let meta = match fs::metadata(&task.src) {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        if meta.nlink() < 2 {
+            continue;
+        }
+
+        let key = (meta.dev(), meta.ino());
+        if let Some(first) = seen.get(&key) {
+            task.kind = Kind::HardLink(first.clone());
+            continue;
+        }
+        seen.insert(key, task.dest.clone());
+    }
+}
+
+#[cfg(not(unix))]
+pub fn detect_hard_links(_tasks: &mut [Task]) {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_tasks() {
+        let groups = vec![
+            PathGroup {
+                path: PathBuf::from("a.txt"),
+                is_file: true,
+                subs: vec![(PathBuf::from("a.txt"), Kind::File)],
+            },
+            PathGroup {
+                path: PathBuf::from("d"),
+                is_file: false,
+                subs: vec![
+                    (PathBuf::from("d/x/b.txt"), Kind::File),
+                    (PathBuf::from("d/x"), Kind::Dir),
+                ],
+            },
+        ];
+
+        let mut tasks = build_tasks(&groups, &PathBuf::from("out"), true);
+        apply_extension(&mut tasks, Mode::Encrypt);
+        let dests: Vec<PathBuf> = tasks.iter().map(|t| t.dest.clone()).collect();
+        assert_eq!(
+            dests,
+            vec![
+                PathBuf::from("out/a.txt.eak"),
+                PathBuf::from("out/d/x/b.txt.eak"),
+                PathBuf::from("out/d/x").join(DIR_RECORD),
+            ]
+        );
+        assert_eq!(src_dirs(&groups), vec![PathBuf::from("d")]);
+    }
+}
@@ -0,0 +1,246 @@
+use std::fmt;
+use std::io;
+
+use byteorder::{BigEndian, ByteOrder};
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use super::util::{from_hex, io_error, to_hex};
+
+// bytes of checksum at the end of printed share, catch typos
+const CHECK_LEN: usize = 4;
+
+/// one share of a secret split by Shamir's scheme, any `threshold` shares
+/// of the same split can recover the secret, fewer ones tell nothing
+#[derive(Clone, Debug, PartialEq)]
+pub struct Share {
+    // random id of the split, shares of different splits not mixed
+    id: u32,
+    threshold: u8,
+    x: u8,
+    y: Vec<u8>,
+}
+
+impl Share {
+    /// parse share printed like `<id>-<threshold>-<x>-<data>-<check>` in hex
+    pub fn parse(text: &str) -> io::Result<Share> {
+        let text: String = text
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_lowercase();
+
+        let pos = text.rfind('-').ok_or_else(|| io_error("share not valid"))?;
+        let (body, check) = (&text[..pos], &text[pos + 1..]);
+        if from_hex(check)? != checksum(body) {
+            return Err(io_error("share checksum not match, typo?"));
+        }
+
+        let parts: Vec<&str> = body.split('-').collect();
+        if parts.len() != 4 {
+            return Err(io_error("share not valid"));
+        }
+
+        let id = u32::from_str_radix(parts[0], 16).map_err(|_| io_error("share id not valid"))?;
+        let threshold = from_hex(parts[1])?;
+        let x = from_hex(parts[2])?;
+        if threshold.len() != 1 || x.len() != 1 || x[0] == 0 {
+            return Err(io_error("share not valid"));
+        }
+
+        Ok(Share {
+            id,
+            threshold: threshold[0],
+            x: x[0],
+            y: from_hex(parts[3])?,
+        })
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold as usize
+    }
+}
+
+impl fmt::Display for Share {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let body = format!(
+            "{:08x}-{:02x}-{:02x}-{}",
+            self.id,
+            self.threshold,
+            self.x,
+            to_hex(&self.y)
+        );
+        write!(f, "{}-{}", body, to_hex(&checksum(&body)))
+    }
+}
+
+/// split secret to `count` shares, any `threshold` of them recover it
+pub fn split(secret: &[u8], threshold: usize, count: usize) -> io::Result<Vec<Share>> {
+    if threshold < 2 || threshold > count || count > 255 {
+        return Err(io_error(&format!(
+            "need 2 <= threshold <= shares <= 255, got {} of {}",
+            threshold, count
+        )));
+    }
+    if secret.is_empty() {
+        return Err(io_error("secret is empty"));
+    }
+
+    let rng = SystemRandom::new();
+    let mut id = [0u8; 4];
+    rng.fill(&mut id)
+        .map_err(|_| io_error("generate share id error"))?;
+
+    let mut shares: Vec<Share> = (1..count + 1)
+        .map(|x| Share {
+            id: BigEndian::read_u32(&id),
+            threshold: threshold as u8,
+            x: x as u8,
+            y: Vec::with_capacity(secret.len()),
+        })
+        .collect();
+
+    // one random polynomial for each byte, constant term is the byte
+    let mut coeffs = vec![0u8; threshold];
+    for &byte in secret {
+        coeffs[0] = byte;
+        rng.fill(&mut coeffs[1..])
+            .map_err(|_| io_error("generate share error"))?;
+
+        for share in &mut shares {
+            let y = coeffs
+                .iter()
+                .rev()
+                .fold(0u8, |acc, &c| gf_mul(acc, share.x) ^ c);
+            share.y.push(y);
+        }
+    }
+
+    coeffs.fill(0);
+    Ok(shares)
+}
+
+/// recover secret from shares of one split, at least threshold of them
+pub fn combine(shares: &[Share]) -> io::Result<Vec<u8>> {
+    let first = shares.first().ok_or_else(|| io_error("no share"))?;
+    if shares
+        .iter()
+        .any(|s| s.id != first.id || s.threshold != first.threshold || s.y.len() != first.y.len())
+    {
+        return Err(io_error("shares not from the same split"));
+    }
+    for (i, share) in shares.iter().enumerate() {
+        if shares[..i].iter().any(|s| s.x == share.x) {
+            return Err(io_error(&format!("share {} given more than once", share.x)));
+        }
+    }
+    if shares.len() < first.threshold() {
+        return Err(io_error(&format!(
+            "need {} shares, only {} given",
+            first.threshold,
+            shares.len()
+        )));
+    }
+
+    // lagrange interpolation at x = 0, sub is xor in GF(256)
+    let shares = &shares[..first.threshold()];
+    let mut secret = vec![0u8; first.y.len()];
+    for (i, share) in shares.iter().enumerate() {
+        let mut basis = 1u8;
+        for (j, other) in shares.iter().enumerate() {
+            if i != j {
+                basis = gf_mul(basis, gf_div(other.x, other.x ^ share.x));
+            }
+        }
+
+        for (b, &y) in secret.iter_mut().zip(share.y.iter()) {
+            *b ^= gf_mul(y, basis);
+        }
+    }
+
+    Ok(secret)
+}
+
+// multiply in GF(256) with AES polynomial, no branch on data
+fn gf_mul(a: u8, b: u8) -> u8 {
+    let (mut a, mut b, mut p) = (a, b, 0u8);
+    for _ in 0..8 {
+        p ^= a & 0u8.wrapping_sub(b & 1);
+        a = (a << 1) ^ (0x1b & 0u8.wrapping_sub(a >> 7));
+        b >>= 1;
+    }
+    p
+}
+
+// a^254 is inverse of a, 0 has no inverse
+fn gf_div(a: u8, b: u8) -> u8 {
+    let mut inv = 1u8;
+    let mut base = b;
+    for bit in 0..8 {
+        if (254 >> bit) & 1 == 1 {
+            inv = gf_mul(inv, base);
+        }
+        base = gf_mul(base, base);
+    }
+    gf_mul(a, inv)
+}
+
+fn checksum(text: &str) -> Vec<u8> {
+    digest::digest(&digest::SHA256, text.as_bytes()).as_ref()[..CHECK_LEN].to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gf() {
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+        assert_eq!(gf_mul(0x57, 0x13), 0xfe);
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, 1), a);
+            assert_eq!(gf_div(a, a), 1);
+            assert_eq!(gf_mul(gf_div(1, a), a), 1);
+        }
+    }
+
+    #[test]
+    fn test_split_combine() {
+        let secret: Vec<u8> = (0..32).collect();
+        let shares = split(&secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        for picks in &[[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let some: Vec<Share> = picks.iter().map(|&i| shares[i].clone()).collect();
+            assert_eq!(combine(&some).unwrap(), secret);
+        }
+        assert_eq!(combine(&shares).unwrap(), secret);
+
+        assert!(combine(&shares[..2]).is_err());
+        assert!(combine(&[shares[0].clone(), shares[0].clone(), shares[1].clone()]).is_err());
+
+        // shares of other split
+        let other = split(&secret, 3, 5).unwrap();
+        assert!(combine(&[shares[0].clone(), shares[1].clone(), other[2].clone()]).is_err());
+
+        assert!(split(&secret, 1, 5).is_err());
+        assert!(split(&secret, 6, 5).is_err());
+    }
+
+    #[test]
+    fn test_share_text() {
+        let shares = split(b"hello kelsi", 2, 3).unwrap();
+        let text = shares[1].to_string();
+        assert_eq!(Share::parse(&text).unwrap(), shares[1]);
+        assert_eq!(
+            Share::parse(&format!(" {} \n", text.to_uppercase())).unwrap(),
+            shares[1]
+        );
+
+        // one char changed
+        let pos = text.len() / 2;
+        let c = if &text[pos..pos + 1] == "0" { "1" } else { "0" };
+        let typo = format!("{}{}{}", &text[..pos], c, &text[pos + 1..]);
+        assert!(Share::parse(&typo).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use base64;
+use ring::rand::SystemRandom;
+use ring::signature::{self, Ed25519KeyPair};
+use untrusted;
+
+use super::util::{expand_tilde_path, io_error};
+
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+
+/// new Ed25519 key pair, returns (private key in pkcs8, public key)
+pub fn generate() -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let rng = SystemRandom::new();
+    let pkcs8 =
+        Ed25519KeyPair::generate_pkcs8(&rng).map_err(|_| io_error("generate signing key error"))?;
+    let pair = Ed25519KeyPair::from_pkcs8(untrusted::Input::from(&pkcs8))
+        .map_err(|_| io_error("generate signing key error"))?;
+
+    Ok((pkcs8.to_vec(), pair.public_key_bytes().to_vec()))
+}
+
+/// signing key from file written by keygen, base64 of pkcs8
+pub fn load_key(path: &str) -> io::Result<Ed25519KeyPair> {
+    let path = expand_tilde_path(path);
+    let text = fs::read_to_string(&*path)
+        .map_err(|e| io_error(&format!("read signing key '{}', {}", path, e)))?;
+
+    base64::decode(text.trim())
+        .ok()
+        .and_then(|pkcs8| Ed25519KeyPair::from_pkcs8(untrusted::Input::from(&pkcs8)).ok())
+        .ok_or_else(|| io_error(&format!("signing key '{}' not valid", path)))
+}
+
+/// public key in base64, or file of it
+pub fn read_public_key(key: &str) -> io::Result<Vec<u8>> {
+    let path = expand_tilde_path(key);
+    let text = if Path::new(&*path).is_file() {
+        fs::read_to_string(&*path)?
+    } else {
+        key.to_owned()
+    };
+
+    match base64::decode(text.trim()) {
+        Ok(ref bytes) if bytes.len() == PUBLIC_KEY_LEN => Ok(bytes.clone()),
+        _ => Err(io_error(&format!("public key '{}' not valid", key))),
+    }
+}
+
+pub fn verify(public_key: &[u8], msg: &[u8], sig: &[u8]) -> bool {
+    signature::verify(
+        &signature::ED25519,
+        untrusted::Input::from(public_key),
+        untrusted::Input::from(msg),
+        untrusted::Input::from(sig),
+    )
+    .is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify() {
+        let (pkcs8, public_key) = generate().unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(untrusted::Input::from(&pkcs8)).unwrap();
+        assert_eq!(pair.public_key_bytes(), &public_key[..]);
+
+        let sig = pair.sign(b"hello kelsi");
+        assert_eq!(sig.as_ref().len(), SIGNATURE_LEN);
+        assert!(verify(&public_key, b"hello kelsi", sig.as_ref()));
+        assert!(!verify(&public_key, b"hello kelsi!", sig.as_ref()));
+
+        let (_, other) = generate().unwrap();
+        assert!(!verify(&other, b"hello kelsi", sig.as_ref()));
+
+        let text = base64::encode(&public_key);
+        assert_eq!(read_public_key(&text).unwrap(), public_key);
+        assert!(read_public_key("not a key").is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Cursor};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use ring::digest;
+
+use super::file::{is_inner_link, FileCrypt};
+use super::task::{check_link_parents, Task};
+use super::util::io_error;
+
+pub const STATE_FILE: &str = ".eakio-sync";
+
+const STATE_VERSION: u8 = 0x02;
+// paths relative to current dir, not safe to delete by
+const STATE_VERSION_V1: u8 = 0x01;
+const HASH_LEN: usize = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub src: PathBuf,
+    pub dest: PathBuf,
+    size: u64,
+    mtime: u64,
+    mtime_nsec: u32,
+    hash: [u8; HASH_LEN],
+}
+
+// sync state, encrypted save in dest dir, record every synced file,
+// src and dest are relative to the src and dest root
+// +---------+-------+---------+---------+------+-------+------------+------+
+// | VERSION | COUNT | SRC LEN |   SRC   | DEST | SIZE  | MTIME/NSEC | HASH |
+// +---------+-------+---------+---------+------+-------+------------+------+
+//                   |<----------------- repeat COUNT times -------------->|
+pub struct SyncState {
+    path: PathBuf,
+    src_root: PathBuf,
+    dest_root: PathBuf,
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl SyncState {
+    pub fn load(
+        src_root: &Path,
+        dest_dir: &Path,
+        file_crypt: &mut FileCrypt,
+    ) -> io::Result<SyncState> {
+        let path = dest_dir.join(STATE_FILE);
+        let mut entries = BTreeMap::new();
+
+        if path.is_file() {
+            let f = File::open(&path)?;
+            let size = f.metadata()?.len() as usize;
+            let mut data = Vec::new();
+            file_crypt
+                .decrypt_stream(&mut BufReader::new(f), size, &mut data)
+                .map_err(|e| io_error(&format!("load sync state {:?}, {}", path, e)))?;
+
+            if data.first() == Some(&STATE_VERSION_V1) {
+                warn!("old sync state {:?} ignored, all files will sync again", path);
+            } else {
+                for entry in decode_entries(&data)? {
+                    entries.insert(entry.src.clone(), entry);
+                }
+            }
+        }
+
+        Ok(SyncState {
+            path,
+            src_root: src_root.to_path_buf(),
+            dest_root: dest_dir.to_path_buf(),
+            entries,
+        })
+    }
+
+    pub fn save(&self, file_crypt: &mut FileCrypt) -> io::Result<()> {
+        let data = encode_entries(self.entries.values())?;
+
+        // write to tmp file first, avoid broken state file
+        let tmp = self.path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            file_crypt.encrypt_stream(&mut Cursor::new(&data), data.len(), &mut writer)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// check task src with state, returns new entry if dest need (re)encrypt
+    pub fn check(&mut self, task: &Task) -> io::Result<Option<Entry>> {
+        let src = relative_to(&task.src, &self.src_root)?;
+        let dest = relative_to(&task.dest, &self.dest_root)?;
+        let meta = fs::metadata(&task.src)?;
+        let (mtime, mtime_nsec) = modified(&meta)?;
+        let mut hash = None;
+
+        if let Some(old) = self.entries.get_mut(&src) {
+            if old.dest == dest && task.dest.is_file() && old.size == meta.len() {
+                if old.mtime == mtime && old.mtime_nsec == mtime_nsec {
+                    return Ok(None);
+                }
+
+                // only touched, content not change
+                let new_hash = file_hash(&task.src)?;
+                if old.hash == new_hash {
+                    old.mtime = mtime;
+                    old.mtime_nsec = mtime_nsec;
+                    return Ok(None);
+                }
+                hash = Some(new_hash);
+            }
+        }
+
+        let hash = match hash {
+            Some(hash) => hash,
+            None => file_hash(&task.src)?,
+        };
+
+        Ok(Some(Entry {
+            src,
+            dest,
+            size: meta.len(),
+            mtime,
+            mtime_nsec,
+            hash,
+        }))
+    }
+
+    pub fn update(&mut self, entry: Entry) {
+        self.entries.insert(entry.src.clone(), entry);
+    }
+
+    /// remove entries which src not in alive (task src paths), returns
+    /// removed entries
+    pub fn prune(&mut self, alive: &HashSet<PathBuf>) -> Vec<Entry> {
+        let alive: HashSet<PathBuf> = alive
+            .iter()
+            .filter_map(|src| src.strip_prefix(&self.src_root).ok())
+            .map(|src| src.to_path_buf())
+            .collect();
+        let dead: Vec<PathBuf> = self.entries
+            .keys()
+            .filter(|src| !alive.contains(*src))
+            .cloned()
+            .collect();
+
+        dead.iter()
+            .filter_map(|src| self.entries.remove(src))
+            .collect()
+    }
+
+    /// dest path of entry, only if it is inside dest root and not
+    /// reached through a symlink
+    pub fn dest_path(&self, entry: &Entry) -> io::Result<PathBuf> {
+        if entry.dest.as_os_str().is_empty() || !is_inner_link(&entry.dest) {
+            return Err(io_error(&format!(
+                "{:?} not inside dest {:?}",
+                entry.dest, self.dest_root
+            )));
+        }
+
+        let path = self.dest_root.join(&entry.dest);
+        check_link_parents(&self.dest_root, &path)?;
+        Ok(path)
+    }
+}
+
+fn relative_to(path: &Path, root: &Path) -> io::Result<PathBuf> {
+    path.strip_prefix(root)
+        .map(|rel| rel.to_path_buf())
+        .map_err(|_| io_error(&format!("{:?} not inside {:?}", path, root)))
+}
+
+fn modified(meta: &fs::Metadata) -> io::Result<(u64, u32)> {
+    let since = meta.modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| io_error(&format!("{}", e)))?;
+
+    Ok((since.as_secs(), since.subsec_nanos()))
+}
+
+fn file_hash(path: &Path) -> io::Result<[u8; HASH_LEN]> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    let mut buf = [0u8; 16 * 1024];
+
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        ctx.update(&buf[..n]);
+    }
+
+    let mut hash = [0u8; HASH_LEN];
+    hash.copy_from_slice(ctx.finish().as_ref());
+    Ok(hash)
+}
+
+fn write_path(buf: &mut Vec<u8>, path: &Path) -> io::Result<()> {
+    let s = path.to_str()
+        .ok_or_else(|| io_error(&format!("path {:?} not valid utf-8", path)))?;
+
+    buf.write_u16::<BigEndian>(s.len() as u16)?;
+    buf.write_all(s.as_bytes())
+}
+
+fn read_path(rdr: &mut Cursor<&[u8]>) -> io::Result<PathBuf> {
+    let len = rdr.read_u16::<BigEndian>()? as usize;
+    let mut bytes = vec![0u8; len];
+    rdr.read_exact(&mut bytes)?;
+
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|e| io_error(&format!("{}", e)))
+}
+
+fn encode_entries<'a, I>(entries: I) -> io::Result<Vec<u8>>
+where
+    I: ExactSizeIterator<Item = &'a Entry>,
+{
+    let mut buf = Vec::new();
+    buf.push(STATE_VERSION);
+    buf.write_u32::<BigEndian>(entries.len() as u32)?;
+
+    for entry in entries {
+        write_path(&mut buf, &entry.src)?;
+        write_path(&mut buf, &entry.dest)?;
+        buf.write_u64::<BigEndian>(entry.size)?;
+        buf.write_u64::<BigEndian>(entry.mtime)?;
+        buf.write_u32::<BigEndian>(entry.mtime_nsec)?;
+        buf.write_all(&entry.hash)?;
+    }
+
+    Ok(buf)
+}
+
+fn decode_entries(data: &[u8]) -> io::Result<Vec<Entry>> {
+    let mut rdr = Cursor::new(data);
+
+    let version = rdr.read_u8()?;
+    if version != STATE_VERSION {
+        return Err(io_error(&format!(
+            "sync state version '{}' not support",
+            version
+        )));
+    }
+
+    let count = rdr.read_u32::<BigEndian>()?;
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let src = read_path(&mut rdr)?;
+        let dest = read_path(&mut rdr)?;
+        let size = rdr.read_u64::<BigEndian>()?;
+        let mtime = rdr.read_u64::<BigEndian>()?;
+        let mtime_nsec = rdr.read_u32::<BigEndian>()?;
+        let mut hash = [0u8; HASH_LEN];
+        rdr.read_exact(&mut hash)?;
+
+        entries.push(Entry {
+            src,
+            dest,
+            size,
+            mtime,
+            mtime_nsec,
+            hash,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(src: &str, dest: &str) -> Entry {
+        Entry {
+            src: PathBuf::from(src),
+            dest: PathBuf::from(dest),
+            size: 1024,
+            mtime: 1_500_000_000,
+            mtime_nsec: 42,
+            hash: [7u8; HASH_LEN],
+        }
+    }
+
+    fn state(src_root: &str, dest_root: &str) -> SyncState {
+        SyncState {
+            path: PathBuf::from(STATE_FILE),
+            src_root: PathBuf::from(src_root),
+            dest_root: PathBuf::from(dest_root),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_entries() {
+        let entries = vec![entry("a/b.txt", "enc/a/b.txt"), entry("a/中文", "enc/a/中文")];
+
+        let data = encode_entries(entries.iter()).unwrap();
+        let decoded = decode_entries(&data).unwrap();
+        assert_eq!(entries, decoded);
+
+        assert!(decode_entries(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut state = state("src", "enc");
+        state.update(entry("a", "a"));
+        state.update(entry("b", "b"));
+
+        let mut alive = HashSet::new();
+        alive.insert(PathBuf::from("src/a"));
+
+        let removed = state.prune(&alive);
+        assert_eq!(removed, vec![entry("b", "b")]);
+        assert_eq!(state.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_dest_path() {
+        let state = state("src", "enc");
+
+        assert_eq!(
+            state.dest_path(&entry("a", "a/b.eak")).unwrap(),
+            PathBuf::from("enc/a/b.eak")
+        );
+        assert!(state.dest_path(&entry("a", "../b.eak")).is_err());
+        assert!(state.dest_path(&entry("a", "/etc/passwd")).is_err());
+        assert!(state.dest_path(&entry("a", "")).is_err());
+    }
+}
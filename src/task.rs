@@ -1,15 +1,21 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use crossbeam::sync::MsQueue;
+use ring::signature::Ed25519KeyPair;
 use num_cpus;
 use scoped_threadpool;
 
-use super::file::FileCrypt;
+use super::file::{probe_version, FileCrypt};
+use super::kms::KeyProvider;
+use super::manifest::{Manifest, Seal};
+use super::padding::Padding;
+use super::util::{absolute_link_path, absolute_path, copy_owner, io_error, remove_file_secure, sync_dir, sync_file};
 
 type Result<T> = result::Result<T, Error>;
 
@@ -18,55 +24,160 @@ pub enum Error {
     NotFile,
     Skip,
     Exists,
+    // in place encrypt src is encrypted already
+    Encrypted,
     Io(io::Error),
+    RemoveSource(io::Error),
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Mode {
     Encrypt,
     Decrypt,
+    // re-encrypt with new secret in place
+    Rekey,
+    // re-encrypt old version file to latest version in place
+    Upgrade,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Kind {
+    File,
+    // keep symlink itself as link record, not the file it point to
+    Symlink,
+    // src is hard link of other task src, dest link to that task dest
+    HardLink(PathBuf),
+    // encrypt: src dir to dest dir record, decrypt: src dir record to dest dir
+    Dir,
+    // src not encrypted, copy it to dest unchanged
+    Copy,
 }
 
 #[derive(Clone, Debug)]
 pub struct Task {
     pub src: PathBuf,
     pub dest: PathBuf,
+    pub kind: Kind,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Options {
+    pub skip_exists: bool,
+    pub overwrite: bool,
+    pub dry_run: bool,
+    // remove src after encrypted dest verified
+    pub remove_source: bool,
+    // overwrite src before remove
+    pub shred: bool,
+    // keep dir mode in dir record
+    pub keep_mode: bool,
+    // replace src with dest by atomic rename
+    pub in_place: bool,
 }
 
 #[derive(Clone)]
 pub struct TaskRuner<'a> {
     mode: Mode,
-    skip_exists: bool,
-    overwrite: bool,
-    dry_run: bool,
+    opts: Options,
     file_crypt: FileCrypt<'a>,
+    new_secret: &'a [u8],
+    // encrypted dests, shared by cloned runers, for the manifest
+    written: Arc<Mutex<Vec<PathBuf>>>,
+    // dirs below it are never written through symlinks
+    dest_root: Option<PathBuf>,
+    // dest of current task is written by it, removed if task failed
+    dest_created: bool,
 }
 
 impl<'a> TaskRuner<'a> {
-    pub fn new(
-        secret: &'a [u8],
-        mode: Mode,
-        skip_exists: bool,
-        overwrite: bool,
-        dry_run: bool,
-    ) -> TaskRuner<'a> {
+    pub fn new(secret: &'a [u8], mode: Mode, opts: Options) -> TaskRuner<'a> {
         TaskRuner {
             mode,
-            skip_exists,
-            overwrite,
-            dry_run,
+            opts,
             file_crypt: FileCrypt::new(secret),
+            new_secret: &[],
+            written: Arc::new(Mutex::new(Vec::new())),
+            dest_root: None,
+            dest_created: false,
         }
     }
 
-    pub fn simple_run(&mut self, tasks: &[Task]) {
-        let total = tasks.len();
-        for (index, task) in tasks.iter().enumerate() {
-            self.run_task(index + 1, total, task);
+    /// runer re-encrypt files to latest version with the same secret
+    pub fn upgrade(secret: &'a [u8], opts: Options) -> TaskRuner<'a> {
+        TaskRuner {
+            mode: Mode::Upgrade,
+            ..TaskRuner::rekey(secret, secret, opts)
         }
     }
 
-    pub fn parallel_run(&mut self, tasks: &[Task], parallel: i32) {
+    /// runer re-encrypt files with new secret, always in place
+    pub fn rekey(secret: &'a [u8], new_secret: &'a [u8], opts: Options) -> TaskRuner<'a> {
+        TaskRuner {
+            mode: Mode::Rekey,
+            opts: Options {
+                in_place: true,
+                ..opts
+            },
+            file_crypt: FileCrypt::new(secret),
+            new_secret,
+            written: Arc::new(Mutex::new(Vec::new())),
+            dest_root: None,
+            dest_created: false,
+        }
+    }
+
+    /// wrap data keys by kms instead of secret
+    pub fn set_kms(&mut self, kms: &'a dyn KeyProvider) {
+        self.file_crypt.set_kms(kms);
+    }
+
+    /// add recovery code slot to every encrypted entry
+    pub fn set_recovery(&mut self, code: &'a [u8]) {
+        self.file_crypt.set_recovery(code);
+    }
+
+    /// sign every encrypted entry
+    pub fn set_signer(&mut self, signer: &'a Ed25519KeyPair) {
+        self.file_crypt.set_signer(signer);
+    }
+
+    /// dest dir of all tasks, a symlink below it (eg. restored from link
+    /// record) is never followed when writing
+    pub fn set_dest_root(&mut self, root: &Path) {
+        self.dest_root = Some(root.to_path_buf());
+    }
+
+    /// pad every encrypted entry to hide its size
+    pub fn set_padding(&mut self, padding: Padding) {
+        self.file_crypt.set_padding(padding);
+    }
+
+    /// only decrypt files signed by one of them, if not empty
+    pub fn set_trusted(&mut self, trusted: &'a [Vec<u8>]) {
+        self.file_crypt.set_trusted(trusted);
+    }
+
+    /// manifest of encrypted files, written or skipped as exists in all
+    /// runs so far, they must be in the dir of manifest
+    pub fn write_manifest(&self, path: &Path, seal: &Seal) -> io::Result<usize> {
+        let written = self.written.lock().unwrap();
+        let manifest = Manifest::build(path, &written)?;
+        manifest.write(path, seal)?;
+        Ok(manifest.len())
+    }
+
+    /// run tasks one by one, returns every task is success or not
+    pub fn simple_run(&mut self, tasks: &[Task]) -> Vec<bool> {
+        let total = tasks.len();
+        tasks
+            .iter()
+            .enumerate()
+            .map(|(index, task)| self.run_task(index + 1, total, task))
+            .collect()
+    }
+
+    /// run tasks in thread pool, returns every task is success or not
+    pub fn parallel_run(&mut self, tasks: &[Task], parallel: i32) -> Vec<bool> {
         let num_threads = if parallel > 0 {
             parallel as u32
         } else {
@@ -78,64 +189,323 @@ impl<'a> TaskRuner<'a> {
             cache.push(self.clone());
         }
         let cache = Arc::new(cache);
+        let done = Mutex::new(vec![false; tasks.len()]);
 
         let mut pool = scoped_threadpool::Pool::new(num_threads);
         pool.scoped(|scoped| {
             let total = tasks.len();
             for (index, task) in tasks.iter().enumerate() {
                 let cache = Arc::clone(&cache);
+                let done = &done;
                 scoped.execute(move || {
                     let mut this = cache.pop();
-                    this.run_task(index + 1, total, task);
+                    let ok = this.run_task(index + 1, total, task);
+                    done.lock().unwrap()[index] = ok;
                     cache.push(this);
                 });
             }
         });
+
+        done.into_inner().unwrap()
     }
 
-    fn run_task(&mut self, index: usize, total: usize, task: &Task) {
-        if self.dry_run {
+    fn run_task(&mut self, index: usize, total: usize, task: &Task) -> bool {
+        if self.opts.dry_run {
             info!("({}/{}) {}: {} (dry run)", index, total, self.mode, task);
-            return;
+            return false;
         }
+        self.dest_created = false;
         match self.do_task(task) {
-            Ok(()) => info!("({}/{}) {}: {} (success)", index, total, self.mode, task),
+            Ok(()) => {
+                info!("({}/{}) {}: {} (success)", index, total, self.mode, task);
+                self.record_written(task);
+                true
+            }
             Err(e) => {
+                // old ciphertext stays in dest, still listed
+                if let Error::Skip = e {
+                    self.record_written(task);
+                }
                 if let Error::Io(_) = e {
-                    if task.dest.is_file() {
-                        fs::remove_file(&task.dest).unwrap();
-                    }
+                    self.remove_broken_dest(task);
                 }
-                error!("({}/{}) {}: {} ({})", index, total, self.mode, task, e)
+                error!("({}/{}) {}: {} ({})", index, total, self.mode, task, e);
+                false
             }
-        };
+        }
+    }
+
+    // only what this task wrote, never through a symlink. in place dest
+    // maybe src, never created here
+    fn remove_broken_dest(&self, task: &Task) {
+        if !self.dest_created {
+            return;
+        }
+        match fs::symlink_metadata(&task.dest) {
+            Ok(ref meta) if meta.file_type().is_file() => {
+                if let Err(e) = fs::remove_file(&task.dest) {
+                    error!("remove broken dest {:?} ({})", task.dest, e);
+                }
+            }
+            _ => {}
+        }
     }
 
     fn do_task(&mut self, task: &Task) -> Result<()> {
-        if !task.src.is_file() {
+        if self.opts.in_place {
+            return self.do_in_place(task);
+        }
+        if let Mode::Rekey = self.mode {
+            return Err(Error::NotFile);
+        }
+        if let Mode::Upgrade = self.mode {
             return Err(Error::NotFile);
         }
 
-        if task.dest.exists() {
-            if self.skip_exists {
+        let src_ok = match (self.mode, &task.kind) {
+            (_, &Kind::Symlink) => fs::symlink_metadata(&task.src)?.file_type().is_symlink(),
+            (Mode::Encrypt, &Kind::Dir) => task.src.is_dir(),
+            _ => task.src.is_file(),
+        };
+        if !src_ok {
+            return Err(Error::NotFile);
+        }
+        if let Some(ref root) = self.dest_root {
+            check_link_parents(root, &task.dest)?;
+        }
+
+        // restore dir record into exists dir is fine
+        let is_dir_restore = self.mode == Mode::Decrypt && task.kind == Kind::Dir;
+
+        // dest maybe a dangling symlink
+        if !is_dir_restore && fs::symlink_metadata(&task.dest).is_ok() {
+            if self.opts.skip_exists {
                 return Err(Error::Skip);
             }
 
-            if !self.overwrite {
+            if !self.opts.overwrite {
                 return Err(Error::Exists);
             }
+
+            // not write through old symlink or hard link
+            fs::remove_file(&task.dest)?;
         }
 
         let dest_dir = task.dest.parent().unwrap();
         fs::create_dir_all(dest_dir)?;
+        self.dest_created = true;
 
-        match self.mode {
-            Mode::Encrypt => self.file_crypt.encrypt(&task.src, &task.dest)?,
-            Mode::Decrypt => self.file_crypt.decrypt(&task.src, &task.dest)?,
+        match (self.mode, &task.kind) {
+            (_, Kind::HardLink(target)) => fs::hard_link(target, &task.dest)?,
+            (_, Kind::Copy) => {
+                fs::copy(&task.src, &task.dest)?;
+            }
+            (Mode::Encrypt, Kind::Symlink) => self.file_crypt.encrypt_link(&task.src, &task.dest)?,
+            (Mode::Encrypt, Kind::File) => self.file_crypt.encrypt(&task.src, &task.dest)?,
+            (Mode::Encrypt, Kind::Dir) => {
+                self.file_crypt
+                    .encrypt_dir(&task.src, &task.dest, self.opts.keep_mode)?
+            }
+            (Mode::Decrypt, Kind::Symlink) => return Err(Error::NotFile),
+            (Mode::Decrypt, Kind::File) | (Mode::Decrypt, Kind::Dir) => {
+                self.file_crypt.decrypt(&task.src, &task.dest)?
+            }
+            (Mode::Rekey, _) | (Mode::Upgrade, _) => unreachable!(),
+        }
+
+        // hard links of a file are all sources of it
+        let is_file = matches!(task.kind, Kind::File | Kind::HardLink(_));
+        if self.mode == Mode::Encrypt && is_file && self.opts.remove_source {
+            self.remove_source(task)?;
+        }
+
+        Ok(())
+    }
+
+    // write to a tmp file beside dest, then atomic rename it to dest, src
+    // removed at last if it is not dest. so there is always a complete src
+    // or dest, even crashed
+    fn do_in_place(&mut self, task: &Task) -> Result<()> {
+        if task.kind != Kind::File || !task.src.is_file() {
+            return Err(Error::NotFile);
+        }
+
+        // replaced file is a new inode, other names of src keep plain data,
+        // a replaced link is no longer a link
+        if fs::symlink_metadata(&task.src)?.file_type().is_symlink() {
+            return Err(Error::Io(io_error("src is a symlink, not replaced in place")));
+        }
+        if link_count(&task.src)? > 1 {
+            return Err(Error::Io(io_error(
+                "src has other hard links, not replaced in place",
+            )));
+        }
+        // encrypt again only makes it need two passwords
+        if self.mode == Mode::Encrypt && probe_version(&task.src)?.is_some() {
+            return Err(Error::Encrypted);
+        }
+
+        if task.src != task.dest && fs::symlink_metadata(&task.dest).is_ok() {
+            if self.opts.skip_exists {
+                return Err(Error::Skip);
+            }
+
+            if !self.opts.overwrite {
+                return Err(Error::Exists);
+            }
+        }
+
+        let tmp = tmp_path(&task.dest);
+        if let Err(e) = self.write_tmp(task, &tmp) {
+            let _ = fs::remove_file(&tmp);
+            return Err(Error::Io(e));
+        }
+
+        fs::rename(&tmp, &task.dest)?;
+        sync_dir(&task.dest)?;
+
+        if task.src != task.dest {
+            fs::remove_file(&task.src)?;
+            sync_dir(&task.src)?;
         }
 
         Ok(())
     }
+
+    fn write_tmp(&mut self, task: &Task, tmp: &Path) -> io::Result<()> {
+        let meta = fs::metadata(&task.src)?;
+
+        match self.mode {
+            Mode::Encrypt => self.file_crypt.encrypt(&task.src, tmp)?,
+            Mode::Decrypt => self.file_crypt.decrypt(&task.src, tmp)?,
+            Mode::Rekey | Mode::Upgrade => {
+                // envelope file only need new key slots, data not touched
+                let rewrapped = self.mode == Mode::Rekey
+                    && self.file_crypt.rewrap(self.new_secret, &task.src, tmp)?;
+                if !rewrapped {
+                    self.file_crypt.rekey(self.new_secret, &task.src, tmp)?
+                }
+            }
+        }
+
+        copy_owner(&meta, tmp)?;
+        fs::set_permissions(tmp, meta.permissions())?;
+        sync_file(tmp)
+    }
+
+    fn record_written(&self, task: &Task) {
+        if self.mode == Mode::Encrypt {
+            self.written.lock().unwrap().push(task.dest.clone());
+        }
+    }
+
+    // only remove src after dest is durable and can be decrypted to same content
+    fn remove_source(&mut self, task: &Task) -> Result<()> {
+        // shred opens the file the link points to
+        if fs::symlink_metadata(&task.src)?.file_type().is_symlink() {
+            return Err(Error::RemoveSource(io_error("src is a symlink, not removed")));
+        }
+        sync_file(&task.dest)?;
+        self.file_crypt.verify(&task.src, &task.dest)?;
+
+        // data still seen by other hard links, only the last one shredded
+        let shred = self.opts.shred && link_count(&task.src)? < 2;
+        remove_file_secure(&task.src, shred).map_err(Error::RemoveSource)
+    }
+}
+
+#[cfg(unix)]
+fn link_count(path: &Path) -> io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(fs::symlink_metadata(path)?.nlink())
+}
+
+#[cfg(not(unix))]
+fn link_count(_path: &Path) -> io::Result<u64> {
+    Ok(1)
+}
+
+// parent dirs of dest below root must not be symlinks, or writes go
+// where the link points to
+pub fn check_link_parents(root: &Path, dest: &Path) -> io::Result<()> {
+    let rel = match dest.parent().map(|parent| parent.strip_prefix(root)) {
+        Some(Ok(rel)) => rel,
+        _ => return Ok(()),
+    };
+
+    let mut path = root.to_path_buf();
+    for comp in rel.components() {
+        path.push(comp);
+        if let Ok(meta) = fs::symlink_metadata(&path) {
+            if meta.file_type().is_symlink() {
+                return Err(io_error(&format!("{:?} is a symlink, not write through it", path)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// hidden tmp file in the same dir of path, rename to path is atomic
+pub fn tmp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap().to_string_lossy();
+    path.with_file_name(format!(".{}.eakio-tmp", name))
+}
+
+/// check tasks before run, dest inside src dir, src same as dest and
+/// different src write to same dest are not allowed. duplicated src
+/// (eg. overlapping globs) only keep the first one
+pub fn validate_tasks(
+    src_dirs: &[PathBuf],
+    dest: &Path,
+    tasks: Vec<Task>,
+) -> io::Result<Vec<Task>> {
+    let dest_abs = absolute_path(dest)?;
+    for dir in src_dirs {
+        if dest_abs.starts_with(absolute_path(dir)?) {
+            return Err(io_error(&format!(
+                "dest {:?} inside src {:?}, will be processed again",
+                dest, dir
+            )));
+        }
+    }
+
+    let mut srcs = HashMap::<PathBuf, PathBuf>::new();
+    let mut dests = HashMap::<PathBuf, PathBuf>::new();
+    let mut checked = Vec::with_capacity(tasks.len());
+
+    for task in tasks {
+        // link src is stored as link, dest is replaced not written through
+        let src = match task.kind {
+            Kind::Symlink => absolute_link_path(&task.src)?,
+            _ => absolute_path(&task.src)?,
+        };
+        let dest = absolute_link_path(&task.dest)?;
+
+        if src == dest {
+            return Err(io_error(&format!("{} src and dest are same", task)));
+        }
+
+        if let Some(first_dest) = srcs.get(&src) {
+            if *first_dest != dest {
+                warn!("{} (skip duplicated src)", task);
+            }
+            continue;
+        }
+
+        if let Some(other) = dests.get(&dest) {
+            return Err(io_error(&format!(
+                "{:?} and {:?} write to same dest {:?}",
+                other, task.src, task.dest
+            )));
+        }
+
+        srcs.insert(src, dest.clone());
+        dests.insert(dest, task.src.clone());
+        checked.push(task);
+    }
+
+    Ok(checked)
 }
 
 impl fmt::Display for Error {
@@ -144,7 +514,9 @@ impl fmt::Display for Error {
             Error::NotFile => write!(f, "not file"),
             Error::Skip => write!(f, "skip exists"),
             Error::Exists => write!(f, "local file exists"),
+            Error::Encrypted => write!(f, "skip already encrypted"),
             Error::Io(ref e) => write!(f, "{}", e),
+            Error::RemoveSource(ref e) => write!(f, "remove source, {}", e),
         }
     }
 }
@@ -154,6 +526,8 @@ impl fmt::Display for Mode {
         match *self {
             Mode::Encrypt => write!(f, "encrypt"),
             Mode::Decrypt => write!(f, "decrypt"),
+            Mode::Rekey => write!(f, "rekey"),
+            Mode::Upgrade => write!(f, "upgrade"),
         }
     }
 }
@@ -169,3 +543,108 @@ impl From<io::Error> for Error {
         Error::Io(err)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{validate_tasks, Kind, Task};
+
+    fn task(src: &str, dest: &str) -> Task {
+        Task {
+            src: PathBuf::from(src),
+            dest: PathBuf::from(dest),
+            kind: Kind::File,
+        }
+    }
+
+    #[test]
+    fn test_validate_tasks() {
+        let dest = PathBuf::from("target/enc/");
+
+        // duplicated src removed
+        let tasks = vec![
+            task("src/lib.rs", "target/enc/lib.rs"),
+            task("src/../src/lib.rs", "target/enc/lib.rs"),
+            task("src/lib.rs", "target/enc/src/lib.rs"),
+            task("src/file.rs", "target/enc/file.rs"),
+        ];
+        let checked = validate_tasks(&[], &dest, tasks).unwrap();
+        assert_eq!(checked.len(), 2);
+
+        // same dest
+        let tasks = vec![
+            task("src/lib.rs", "target/enc/lib.rs"),
+            task("src/file.rs", "target/enc/./lib.rs"),
+        ];
+        assert!(validate_tasks(&[], &dest, tasks).is_err());
+
+        // src is dest
+        let tasks = vec![task("src/lib.rs", "src/lib.rs")];
+        assert!(validate_tasks(&[], &dest, tasks).is_err());
+
+        // dest inside src
+        let src_dirs = vec![PathBuf::from("src")];
+        let dest = PathBuf::from("src/enc/");
+        assert!(validate_tasks(&src_dirs, &dest, Vec::new()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_link_parents() {
+        use std::env;
+        use std::fs;
+        use std::os::unix::fs::symlink;
+        use std::process;
+
+        use super::check_link_parents;
+
+        let root = env::temp_dir().join(format!("eakio-test-link-parents-{}", process::id()));
+        fs::create_dir_all(root.join("dir")).unwrap();
+        symlink("/etc", root.join("link")).unwrap();
+
+        assert!(check_link_parents(&root, &root.join("dir/a")).is_ok());
+        assert!(check_link_parents(&root, &root.join("new/a")).is_ok());
+        // link itself as dest is replaced, not followed
+        assert!(check_link_parents(&root, &root.join("link")).is_ok());
+        assert!(check_link_parents(&root, &root.join("link/passwd")).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_failed_task_not_remove_through_link() {
+        use std::env;
+        use std::fs;
+        use std::os::unix::fs::symlink;
+        use std::process;
+
+        use super::{Mode, Options, TaskRuner};
+
+        let root = env::temp_dir().join(format!("eakio-test-failed-task-{}", process::id()));
+        let outside = root.join("outside");
+        let dest = root.join("dest");
+        fs::create_dir_all(&outside).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(outside.join("a"), b"keep me").unwrap();
+        fs::write(root.join("a"), b"plain").unwrap();
+        symlink(&outside, dest.join("link")).unwrap();
+
+        let opts = Options {
+            overwrite: true,
+            ..Default::default()
+        };
+        let mut runer = TaskRuner::new(b"secret", Mode::Encrypt, opts);
+        runer.set_dest_root(&dest);
+        let task = Task {
+            src: root.join("a"),
+            dest: dest.join("link").join("a"),
+            kind: Kind::File,
+        };
+        assert_eq!(runer.simple_run(&[task]), vec![false]);
+        assert_eq!(fs::read(outside.join("a")).unwrap(), b"keep me");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
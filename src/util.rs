@@ -1,7 +1,11 @@
 use std::borrow::Cow;
+use std::cmp;
 use std::env;
 use std::fmt;
+use std::fs::{self, File, OpenOptions};
 use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 
 use ansi_term::Color;
 use env_logger::LogBuilder;
@@ -51,7 +55,7 @@ pub fn init_logger() {
 }
 
 /// expand path like ~/xxx
-pub fn expand_tilde_path(path: &str) -> Cow<str> {
+pub fn expand_tilde_path(path: &str) -> Cow<'_, str> {
     if !path.starts_with('~') {
         return path.into();
     }
@@ -71,14 +75,189 @@ pub fn expand_tilde_path(path: &str) -> Cow<str> {
     }
 }
 
+/// move file, fallback to copy and remove if rename failed (eg. cross device)
+pub fn move_file(src: &Path, dest: &Path) -> io::Result<()> {
+    if let Some(dir) = dest.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    if fs::rename(src, dest).is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(src, dest)?;
+    fs::remove_file(src)
+}
+
+/// flush file data and its dir entry to disk
+pub fn sync_file(path: &Path) -> io::Result<()> {
+    File::open(path)?.sync_all()?;
+    sync_dir(path)
+}
+
+/// flush dir entry of path to disk, only needed on unix
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        if let Some(dir) = path.parent() {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            File::open(dir)?.sync_all()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// change path owner to the owner in meta
+#[cfg(unix)]
+pub fn copy_owner(meta: &fs::Metadata, path: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+
+    let c_path =
+        CString::new(path.as_os_str().as_bytes()).map_err(|e| io_error(&format!("{}", e)))?;
+
+    let ret = unsafe { ::libc::chown(c_path.as_ptr(), meta.uid(), meta.gid()) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn copy_owner(_meta: &fs::Metadata, _path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// create a new file only owner can read, fails if it exists
+#[cfg(unix)]
+pub fn create_private(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+pub fn create_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// create a new dir only owner can enter, fails if it exists
+#[cfg(unix)]
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    fs::DirBuilder::new().mode(0o700).create(path)
+}
+
+#[cfg(not(unix))]
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    fs::create_dir(path)
+}
+
+/// remove file, if shred overwrite file content with zero before unlink.
+/// shred is best-effort, copy-on-write or journaling fs may keep old data
+pub fn remove_file_secure(path: &Path, shred: bool) -> io::Result<()> {
+    if shred {
+        let mut f = OpenOptions::new().write(true).open(path)?;
+        let mut left = f.metadata()?.len();
+        let zeros = [0u8; 64 * 1024];
+        while left > 0 {
+            let n = cmp::min(left, zeros.len() as u64) as usize;
+            f.write_all(&zeros[..n])?;
+            left -= n as u64;
+        }
+        f.sync_all()?;
+    }
+
+    fs::remove_file(path)
+}
+
+/// absolute path, canonicalized if it exists, or only the exists part
+/// of its parent dir
+pub fn absolute_path(path: &Path) -> io::Result<PathBuf> {
+    match fs::canonicalize(path) {
+        Ok(abs) => Ok(abs),
+        Err(_) => absolute_link_path(path),
+    }
+}
+
+/// absolute path of the link itself, not where it points to. only the
+/// exists part of its parent dir is canonicalized, last component kept
+pub fn absolute_link_path(path: &Path) -> io::Result<PathBuf> {
+    let name = match path.file_name() {
+        Some(name) => name,
+        None => return fs::canonicalize(path),
+    };
+
+    let mut base = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    // walk up to find the exists dir
+    let mut rest = Vec::new();
+    let mut abs = loop {
+        match fs::canonicalize(&base) {
+            Ok(abs) => break abs,
+            Err(e) => {
+                match base.file_name() {
+                    Some(name) => rest.push(name.to_os_string()),
+                    None => return Err(e),
+                }
+                base.pop();
+                if base.as_os_str().is_empty() {
+                    base = PathBuf::from(".");
+                }
+            }
+        }
+    };
+
+    for comp in rest.iter().rev() {
+        abs.push(comp);
+    }
+    abs.push(name);
+
+    Ok(abs)
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(text: &str) -> io::Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return Err(io_error("hex not valid"));
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            text.get(i..i + 2)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(|| io_error("hex not valid"))
+        })
+        .collect()
+}
+
 #[inline]
 pub fn io_error(desc: &str) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, desc)
+    io::Error::other(desc)
 }
 
 #[cfg(test)]
 mod test {
     use std::env;
+    use std::path::Path;
 
     #[test]
     fn test_expand_tilde_path() {
@@ -94,4 +273,33 @@ mod test {
             env::set_var("HOME", old);
         }
     }
+
+    #[test]
+    fn test_absolute_path() {
+        let cwd = env::current_dir().unwrap().canonicalize().unwrap();
+
+        let path = super::absolute_path(Path::new("not-exists-dir/a/b.txt")).unwrap();
+        assert_eq!(path, cwd.join("not-exists-dir/a/b.txt"));
+
+        let path = super::absolute_path(Path::new("src/../Cargo.toml")).unwrap();
+        assert_eq!(path, cwd.join("Cargo.toml"));
+
+        let path = super::absolute_path(Path::new("Cargo.toml")).unwrap();
+        assert_eq!(path, cwd.join("Cargo.toml"));
+        #[cfg(unix)]
+        {
+            use std::fs;
+            use std::os::unix::fs::symlink;
+            use std::process;
+
+            let link = env::temp_dir().join(format!("eakio-test-abs-link-{}", process::id()));
+            symlink(cwd.join("src"), &link).unwrap();
+            assert_eq!(super::absolute_path(&link).unwrap(), cwd.join("src"));
+            assert_eq!(
+                super::absolute_link_path(&link).unwrap(),
+                env::temp_dir().canonicalize().unwrap().join(link.file_name().unwrap())
+            );
+            fs::remove_file(&link).unwrap();
+        }
+    }
 }
@@ -0,0 +1,111 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+use notify::{self, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::task::check_link_parents;
+use super::util::io_error;
+
+pub struct DirWatcher {
+    root: PathBuf,
+    hidden: bool,
+    rx: Receiver<DebouncedEvent>,
+    // keep watcher alive
+    _watcher: RecommendedWatcher,
+}
+
+impl DirWatcher {
+    /// watch root dir recursive, events are debounced by delay,
+    /// a file only reported after no write in delay
+    pub fn new(root: &Path, delay: Duration, hidden: bool) -> io::Result<DirWatcher> {
+        let (tx, rx) = channel();
+        let mut watcher = notify::watcher(tx, delay).map_err(notify_error)?;
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .map_err(notify_error)?;
+
+        Ok(DirWatcher {
+            root: root.to_path_buf(),
+            hidden,
+            rx,
+            _watcher: watcher,
+        })
+    }
+
+    /// block until some files created or modified, returns them
+    pub fn next_batch(&self) -> io::Result<Vec<PathBuf>> {
+        let mut paths = BTreeSet::new();
+
+        let event = self.rx
+            .recv()
+            .map_err(|_| io_error("watcher channel closed"))?;
+        self.collect(event, &mut paths);
+
+        // other files settled at the same time
+        while let Ok(event) = self.rx.try_recv() {
+            self.collect(event, &mut paths);
+        }
+
+        Ok(paths.into_iter().filter(|p| self.is_regular(p)).collect())
+    }
+
+    // not follow symlinks, as scan does, the file or a dir on the way
+    fn is_regular(&self, path: &Path) -> bool {
+        match fs::symlink_metadata(path) {
+            Ok(ref meta) if meta.file_type().is_symlink() => {
+                warn!("Skip symlink {:?}", path);
+                false
+            }
+            Ok(ref meta) if meta.file_type().is_file() => {
+                if let Err(e) = check_link_parents(&self.root, path) {
+                    warn!("Skip {}", e);
+                    return false;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn collect(&self, event: DebouncedEvent, paths: &mut BTreeSet<PathBuf>) {
+        let path = match event {
+            DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => path,
+            DebouncedEvent::Rename(_, to) => to,
+            DebouncedEvent::Error(e, path) => {
+                warn!("watch {:?} error: {}", path, e);
+                return;
+            }
+            _ => return,
+        };
+
+        if !path.starts_with(&self.root) {
+            return;
+        }
+        if self.hidden || !self.is_hidden(&path) {
+            paths.insert(path);
+        }
+    }
+
+    fn is_hidden(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.root)
+            .unwrap()
+            .components()
+            .any(|c| {
+                c.as_os_str()
+                    .to_str()
+                    .map(|s| s.starts_with('.'))
+                    .unwrap_or(false)
+            })
+    }
+}
+
+fn notify_error(err: notify::Error) -> io::Error {
+    match err {
+        notify::Error::Io(e) => e,
+        e => io_error(&format!("{}", e)),
+    }
+}